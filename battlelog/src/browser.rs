@@ -0,0 +1,357 @@
+//! The Battlelog server browser, for finding servers and their GUIDs.
+//!
+//! Battlelog filters by name, region, game mode and map on its side. The same filters and the
+//! player count are checked again on the typed results, since the browser treats some of them
+//! as hints rather than hard limits.
+
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::{GameMode, Map};
+
+/// Most servers the browser returns per request.
+pub const BROWSER_PAGE_SIZE: usize = 60;
+
+/// Most pages fetched per search. The browser doesn't filter on everything the query can, so a
+/// search that matches few servers would otherwise page through the whole browser.
+pub const MAX_BROWSER_PAGES: usize = 10;
+
+/// Region a server is hosted in, as used by the server browser.
+///
+/// Battlelog filters by the sum of the ids of the wanted regions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Region {
+    NorthAmerica,
+    SouthAmerica,
+    Antarctica,
+    Africa,
+    Europe,
+    Asia,
+    Oceania,
+    /// Region id that isn't in the lookup table.
+    Other(u32),
+}
+
+impl Region {
+    pub const ALL: &'static [Region] = &[
+        Region::NorthAmerica,
+        Region::SouthAmerica,
+        Region::Antarctica,
+        Region::Africa,
+        Region::Europe,
+        Region::Asia,
+        Region::Oceania,
+    ];
+
+    pub fn id(&self) -> u32 {
+        match self {
+            Region::NorthAmerica => 1,
+            Region::SouthAmerica => 2,
+            Region::Antarctica => 4,
+            Region::Africa => 8,
+            Region::Europe => 16,
+            Region::Asia => 32,
+            Region::Oceania => 64,
+            Region::Other(id) => *id,
+        }
+    }
+
+    /// Short name as used on Battlelog, for example `"EU"`, `"?"` for an unknown id.
+    pub fn short_name(&self) -> &'static str {
+        match self {
+            Region::NorthAmerica => "NAm",
+            Region::SouthAmerica => "SAm",
+            Region::Antarctica => "AC",
+            Region::Africa => "AF",
+            Region::Europe => "EU",
+            Region::Asia => "Asia",
+            Region::Oceania => "OC",
+            Region::Other(_) => "?",
+        }
+    }
+
+    /// `"Unknown"` for an id that isn't in the lookup table, the [`Display`](fmt::Display)
+    /// includes the id.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Region::NorthAmerica => "North America",
+            Region::SouthAmerica => "South America",
+            Region::Antarctica => "Antarctica",
+            Region::Africa => "Africa",
+            Region::Europe => "Europe",
+            Region::Asia => "Asia",
+            Region::Oceania => "Oceania",
+            Region::Other(_) => "Unknown",
+        }
+    }
+}
+
+impl From<u32> for Region {
+    fn from(id: u32) -> Self {
+        Region::ALL.iter().copied().find(|region| region.id() == id).unwrap_or(Region::Other(id))
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Region::Other(id) => write!(f, "Region {}", id),
+            region => f.write_str(region.name()),
+        }
+    }
+}
+
+/// A region name that isn't known, see [`Region::from_str`].
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("unknown region {0:?}, expected one of na, sa, antarctica, africa, eu, asia or oceania")]
+pub struct UnknownRegion(pub String);
+
+impl FromStr for Region {
+    type Err = UnknownRegion;
+
+    /// Accepts the name or the short name in any case, with or without spaces.
+    ///
+    /// ```
+    /// use battlelog::Region;
+    ///
+    /// assert_eq!(Ok(Region::Europe), "eu".parse());
+    /// assert_eq!(Ok(Region::NorthAmerica), "North America".parse());
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized: String = s.chars().filter(|c| !c.is_whitespace() && *c != '_').collect::<String>().to_lowercase();
+        match normalized.as_str() {
+            "na" | "nam" | "northamerica" => Ok(Region::NorthAmerica),
+            "sa" | "sam" | "southamerica" => Ok(Region::SouthAmerica),
+            "ac" | "antarctica" => Ok(Region::Antarctica),
+            "af" | "africa" => Ok(Region::Africa),
+            "eu" | "europe" => Ok(Region::Europe),
+            "as" | "asia" => Ok(Region::Asia),
+            "oc" | "oceania" => Ok(Region::Oceania),
+            _ => Err(UnknownRegion(s.to_string())),
+        }
+    }
+}
+
+/// Ids of the game modes in the server browser, which uses a bit per mode instead of the names
+/// Keeper reports.
+const BROWSER_GAME_MODES: &[(GameMode, u32)] = &[
+    (GameMode::ConquestSmall, 1),
+    (GameMode::RushLarge, 2),
+    (GameMode::SquadDeathmatch, 8),
+    (GameMode::TeamDeathmatch, 32),
+    (GameMode::ConquestLarge, 64),
+    (GameMode::Domination, 1024),
+    (GameMode::GunMaster, 2048),
+    (GameMode::AirSuperiority, 131072),
+    (GameMode::CarrierAssaultSmall, 262144),
+    (GameMode::CaptureTheFlag, 524288),
+    (GameMode::Obliteration, 2097152),
+    (GameMode::Defuse, 8388608),
+    (GameMode::SquadObliteration, 16777216),
+    (GameMode::ChainLink, 33554432),
+    (GameMode::CarrierAssaultLarge, 134217728),
+];
+
+impl GameMode {
+    /// The id of the game mode in the server browser, `None` for the unknown ones.
+    pub fn browser_id(&self) -> Option<u32> {
+        BROWSER_GAME_MODES.iter().find(|(mode, _)| mode == self).map(|(_, id)| *id)
+    }
+
+    /// The game mode of a server browser id, the unknown ones become `Other` with the id.
+    pub fn from_browser_id(id: u32) -> Self {
+        BROWSER_GAME_MODES
+            .iter()
+            .find(|(_, browser_id)| *browser_id == id)
+            .map(|(mode, _)| mode.clone())
+            .unwrap_or_else(|| GameMode::Other(id.to_string()))
+    }
+}
+
+/// Filters of a server browser search, every one that is set has to match.
+///
+/// # Examples
+///
+/// ```
+/// use battlelog::{GameMode, Region, ServerQuery};
+///
+/// let query = ServerQuery::new()
+///     .name("BattleFox")
+///     .region(Region::Europe)
+///     .game_mode(GameMode::ConquestLarge)
+///     .min_players(1);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ServerQuery {
+    /// Part of the server name, case insensitive
+    pub name: Option<String>,
+    /// Any of these regions, all of them if empty
+    pub regions: Vec<Region>,
+    /// Any of these game modes, all of them if empty
+    pub game_modes: Vec<GameMode>,
+    /// Any of these maps, all of them if empty
+    pub maps: Vec<Map>,
+    pub min_players: Option<u16>,
+    pub max_players: Option<u16>,
+    /// Most servers to return, defaults to [`BROWSER_PAGE_SIZE`]
+    pub limit: Option<usize>,
+}
+
+impl ServerQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Adds a region, can be given several times.
+    pub fn region(mut self, region: Region) -> Self {
+        self.regions.push(region);
+        self
+    }
+
+    /// Adds a game mode, can be given several times.
+    pub fn game_mode(mut self, game_mode: GameMode) -> Self {
+        self.game_modes.push(game_mode);
+        self
+    }
+
+    /// Adds a map, can be given several times.
+    pub fn map(mut self, map: Map) -> Self {
+        self.maps.push(map);
+        self
+    }
+
+    pub fn min_players(mut self, players: u16) -> Self {
+        self.min_players = Some(players);
+        self
+    }
+
+    pub fn max_players(mut self, players: u16) -> Self {
+        self.max_players = Some(players);
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Whether the server passes every filter.
+    pub fn matches(&self, server: &ServerSummary) -> bool {
+        let name = match &self.name {
+            Some(name) => server.name.to_lowercase().contains(&name.to_lowercase()),
+            None => true,
+        };
+        name && (self.regions.is_empty() || self.regions.contains(&server.region))
+            && (self.game_modes.is_empty() || self.game_modes.contains(&server.game_mode))
+            && (self.maps.is_empty() || self.maps.contains(&server.map))
+            && self.min_players.is_none_or(|min| server.players >= min)
+            && self.max_players.is_none_or(|max| server.players <= max)
+    }
+
+    /// The query string of a page of the search.
+    pub(crate) fn params(&self, offset: usize) -> Vec<(&'static str, String)> {
+        let mut params = vec![
+            ("filtered", "1".to_string()),
+            ("expand", "1".to_string()),
+            ("offset", offset.to_string()),
+            ("count", BROWSER_PAGE_SIZE.to_string()),
+        ];
+        if let Some(name) = &self.name {
+            params.push(("q", name.to_string()));
+        }
+        if !self.regions.is_empty() {
+            params.push(("regions", self.regions.iter().map(Region::id).sum::<u32>().to_string()));
+        }
+        let game_modes: u32 = self.game_modes.iter().filter_map(GameMode::browser_id).sum();
+        if game_modes > 0 {
+            params.push(("gamemodes", game_modes.to_string()));
+        }
+        for map in &self.maps {
+            params.push(("maps", map.id().to_string()));
+        }
+        params
+    }
+}
+
+/// A server as listed in the server browser.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerSummary {
+    /// What Keeper and the other server pages know the server by
+    pub guid: String,
+    pub name: String,
+    pub region: Region,
+    /// Two letter country code of the server location
+    pub country: String,
+    pub map: Map,
+    pub game_mode: GameMode,
+    /// Players in the game, without the queue, commanders and spectators
+    pub players: u16,
+    pub max_players: u16,
+    /// Players in the join queue
+    pub queued: u16,
+    pub has_password: bool,
+}
+
+impl From<BrowserServer> for ServerSummary {
+    fn from(server: BrowserServer) -> Self {
+        let slots = |slot: &str| server.slots.get(slot).copied().unwrap_or_default();
+        ServerSummary {
+            region: Region::from(server.region),
+            map: Map::from(server.map.as_str()),
+            game_mode: GameMode::from_browser_id(server.map_mode),
+            players: slots(SOLDIER_SLOTS).current,
+            max_players: slots(SOLDIER_SLOTS).max,
+            queued: slots(QUEUE_SLOTS).current,
+            has_password: server.has_password,
+            guid: server.guid,
+            name: server.name,
+            country: server.country,
+        }
+    }
+}
+
+/// Keys of [`BrowserServer::slots`].
+const QUEUE_SLOTS: &str = "1";
+const SOLDIER_SLOTS: &str = "2";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerBrowserResponse {
+    pub r#type: String,
+    pub message: String,
+    pub data: Vec<BrowserServer>,
+}
+
+/// A server as the browser returns it, see [`ServerSummary`] for the typed version.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BrowserServer {
+    pub guid: String,
+    pub name: String,
+    /// Map id, for example `"MP_Prison"`
+    pub map: String,
+    /// Game mode id of the browser, see [`GameMode::from_browser_id`]
+    pub map_mode: u32,
+    pub region: u32,
+    #[serde(default)]
+    pub country: String,
+    #[serde(default)]
+    pub has_password: bool,
+    /// Keyed by the kind of slot: `"1"` the queue, `"2"` the soldiers, `"4"` the commanders and
+    /// `"8"` the spectators
+    #[serde(default)]
+    pub slots: HashMap<String, Slots>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Slots {
+    pub current: u16,
+    pub max: u16,
+}
@@ -0,0 +1,322 @@
+use std::time::Duration;
+
+use http::{HeaderMap, HeaderValue, StatusCode, header::RETRY_AFTER};
+use reqwest::{Proxy, RequestBuilder};
+use serde::de::DeserializeOwned;
+
+use crate::browser::{ServerBrowserResponse, ServerQuery, ServerSummary, BROWSER_PAGE_SIZE, MAX_BROWSER_PAGES};
+use crate::error::{Error, Result};
+use crate::server::{ServerInfo, ServerPageResponse};
+use crate::models::*;
+
+pub const DEFAULT_BATTLELOG_URL: &str = "https://battlelog.battlefield.com";
+pub const DEFAULT_KEEPER_URL: &str = "https://keeper.battlelog.com";
+pub const DEFAULT_USER_AGENT: &str = "BattleFox";
+
+/// Client for the Battlelog and Keeper REST APIs.
+///
+/// Owns a single pooled [`reqwest::Client`], so it should be created once and reused.
+/// Cloning is cheap and shares the underlying connection pool.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use battlelog::BattlelogClient;
+///
+/// # async fn run() -> Result<(), battlelog::Error> {
+/// let client = BattlelogClient::builder()
+///     .user_agent("MyLogger")
+///     .timeout(Duration::from_secs(10))
+///     .build()?;
+///
+/// let data = client.server_snapshot("4d0151b3-81ff-4268-b4e8-5e60d5bc8765").await?;
+/// println!("{}", data.snapshot.current_map);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct BattlelogClient {
+    client: reqwest::Client,
+    battlelog_url: String,
+    keeper_url: String,
+}
+
+/// Builder for [`BattlelogClient`].
+#[derive(Debug)]
+pub struct BattlelogClientBuilder {
+    battlelog_url: String,
+    keeper_url: String,
+    user_agent: String,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    proxy: Option<Proxy>,
+    headers: HeaderMap,
+}
+
+impl Default for BattlelogClientBuilder {
+    fn default() -> Self {
+        Self {
+            battlelog_url: DEFAULT_BATTLELOG_URL.to_string(),
+            keeper_url: DEFAULT_KEEPER_URL.to_string(),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            timeout: None,
+            connect_timeout: None,
+            proxy: None,
+            headers: HeaderMap::new(),
+        }
+    }
+}
+
+impl BattlelogClientBuilder {
+    /// Base URL of Battlelog, defaults to [`DEFAULT_BATTLELOG_URL`].
+    pub fn battlelog_url(mut self, url: impl Into<String>) -> Self {
+        self.battlelog_url = trim_base_url(url.into());
+        self
+    }
+
+    /// Base URL of the Keeper snapshot service, defaults to [`DEFAULT_KEEPER_URL`].
+    pub fn keeper_url(mut self, url: impl Into<String>) -> Self {
+        self.keeper_url = trim_base_url(url.into());
+        self
+    }
+
+    /// User agent sent with every request, defaults to [`DEFAULT_USER_AGENT`].
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    /// Total timeout of a single request, including reading the body.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout for only the connect phase of a request.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Routes every request through the given proxy.
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Adds a header that is sent with every request.
+    pub fn default_header(mut self, name: &'static str, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Replaces the headers that are sent with every request.
+    pub fn default_headers(mut self, headers: HeaderMap) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    pub fn build(self) -> Result<BattlelogClient> {
+        let mut builder = reqwest::Client::builder()
+            .user_agent(self.user_agent)
+            .default_headers(self.headers);
+
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(proxy) = self.proxy {
+            builder = builder.proxy(proxy);
+        }
+
+        Ok(BattlelogClient {
+            client: builder.build()?,
+            battlelog_url: self.battlelog_url,
+            keeper_url: self.keeper_url,
+        })
+    }
+}
+
+fn trim_base_url(url: String) -> String {
+    url.trim_end_matches('/').to_string()
+}
+
+impl Default for BattlelogClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BattlelogClient {
+    /// Constructs a new `BattlelogClient` with the default settings.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the TLS backend cannot be initialized, same as [`reqwest::Client::new`].
+    pub fn new() -> Self {
+        Self::builder()
+            .build()
+            .expect("BattlelogClient::new()")
+    }
+
+    pub fn builder() -> BattlelogClientBuilder {
+        BattlelogClientBuilder::default()
+    }
+
+    pub fn battlelog_url(&self) -> &str {
+        &self.battlelog_url
+    }
+
+    pub fn keeper_url(&self) -> &str {
+        &self.keeper_url
+    }
+
+    pub async fn search_user(&self, soldier_name: &str) -> Result<SearchResult> {
+        let params = [("query", soldier_name.to_owned())];
+        let request = self.client
+            .post(format!("{}/bf4/search/query/", self.battlelog_url))
+            .form(&params);
+
+        let mut js: SearchResponse = self.send(request, || format!("user {}", soldier_name)).await?;
+        //println!("SearchResponse: {:#?}", js);
+
+        for i in 0..js.data.len() {
+            let result = &js.data[i];
+            //println!("User: {:#?}", result);
+
+            // Requires correct persona name. Apparently default parameters or overrides are not supported so not adding support for partial names now.
+            if result.persona_name.ne(&soldier_name) {
+                //println!("Not a correct persona");
+                continue;
+            }
+
+            if result.namespace != "cem_ea_id" {
+                //println!("Not a PC namespace");
+                continue;
+            }
+
+            for val in result.games.values() {
+                if val.parse::<i32>().unwrap_or(0) & 2048 == 0 {
+                    continue;
+                }
+                //println!("Has BF4");
+
+                return Ok(js.data.remove(i))
+            }
+        }
+
+        Err(Error::NotFound(format!("user {}", soldier_name)))
+    }
+
+    pub async fn server_snapshot(&self, server_guid: &str) -> Result<KeeperResponse> {
+        let request = self.client
+            .get(format!("{}/snapshot/{}", self.keeper_url, server_guid));
+
+        self.send(request, || format!("server {}", server_guid)).await
+    }
+
+    /// Searches the server browser for the servers that match the query.
+    ///
+    /// Fetches pages until the browser runs out of servers, [`ServerQuery::limit`] is reached or
+    /// [`MAX_BROWSER_PAGES`] were fetched.
+    pub async fn server_browser(&self, query: &ServerQuery) -> Result<Vec<ServerSummary>> {
+        let limit = query.limit.unwrap_or(BROWSER_PAGE_SIZE);
+        let mut servers = Vec::new();
+        let mut offset = 0;
+
+        for _ in 0..MAX_BROWSER_PAGES {
+            if servers.len() >= limit {
+                break;
+            }
+            let request = self.client
+                .get(format!("{}/bf4/servers/getServers/pc/", self.battlelog_url))
+                .query(&query.params(offset))
+                .headers(ajax_headers());
+            let page: ServerBrowserResponse = self.send(request, || "server browser".to_string()).await?;
+
+            let count = page.data.len();
+            servers.extend(page.data.into_iter().map(ServerSummary::from).filter(|server| query.matches(server)));
+            if count < BROWSER_PAGE_SIZE {
+                break;
+            }
+            offset += count;
+        }
+
+        servers.truncate(limit);
+        Ok(servers)
+    }
+
+    /// Details of a server from its Battlelog page: name, settings, rotation and so on.
+    pub async fn server_info(&self, server_guid: &str) -> Result<ServerInfo> {
+        let request = self.client
+            .get(format!("{}/bf4/servers/show/pc/{}/", self.battlelog_url, server_guid))
+            .headers(ajax_headers());
+
+        let page: ServerPageResponse = self.send(request, || format!("server {}", server_guid)).await?;
+        Ok(ServerInfo::from(page.context.server))
+    }
+
+    pub async fn ingame_metadata(&self, persona_id: u64) -> Result<IngameMetadataResponse> {
+        let request = self.client
+            .get(format!("{}/api/bf4/pc/persona/1/{}/ingame_metadata", self.battlelog_url, persona_id));
+
+        self.send(request, || format!("persona {}", persona_id)).await
+    }
+
+    pub async fn get_user(&self, persona_id: &str) -> Result<StatsResponse> {
+        let request = self.client
+            .get(format!(
+                "{}/bf4/soldier/SOLDIER/stats/{}/pc/",
+                self.battlelog_url, persona_id
+            ))
+            .headers(ajax_headers());
+
+        self.send(request, || format!("persona {}", persona_id)).await
+    }
+
+    /// Sends the request and deserializes the JSON body, mapping failures to [`Error`].
+    ///
+    /// `resource` describes what was requested and is only evaluated for [`Error::NotFound`].
+    async fn send<T, F>(&self, request: RequestBuilder, resource: F) -> Result<T>
+    where
+        T: DeserializeOwned,
+        F: FnOnce() -> String,
+    {
+        let res = request.send().await?;
+
+        let status = res.status();
+        let retry_after = res
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|val| val.to_str().ok())
+            .and_then(|val| val.trim().parse::<u64>().ok())
+            .map(Duration::from_secs);
+
+        let data_str = res
+            .text()
+            .await?;
+        //println!("{}", data_str);
+
+        match status {
+            StatusCode::OK => {}
+            StatusCode::NOT_FOUND => return Err(Error::NotFound(resource())),
+            StatusCode::TOO_MANY_REQUESTS => return Err(Error::RateLimited { retry_after }),
+            _ => return Err(Error::Http { status, body: data_str }),
+        }
+
+        let de = &mut serde_json::Deserializer::from_str(&data_str);
+        serde_path_to_error::deserialize(de).map_err(|err| Error::deserialize(err, data_str))
+    }
+}
+
+/// Headers that make Battlelog return the page data as JSON instead of HTML.
+fn ajax_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("X-AjaxNavigation", HeaderValue::from_static("1"));
+    headers.insert("X-Requested-With", HeaderValue::from_static("XMLHttpRequest"));
+    headers
+}
+
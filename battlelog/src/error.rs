@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+use http::StatusCode;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The server answered with a status code that isn't handled by the other variants.
+    #[error("unexpected HTTP status {status}: {body}")]
+    Http { status: StatusCode, body: String },
+
+    /// The request could not be sent or the response could not be read.
+    #[error("request failed: {0}")]
+    Transport(#[from] reqwest::Error),
+
+    /// The response was received but didn't match the expected schema.
+    #[error("failed to deserialize response at `{path}`: {source}")]
+    Deserialize {
+        /// Path to the offending value, for example `snapshot.teamInfo.1.faction`.
+        path: String,
+        source: serde_json::Error,
+        /// The raw response body.
+        payload: String,
+    },
+
+    /// The requested server, persona or user doesn't exist.
+    #[error("{0} not found")]
+    NotFound(String),
+
+    /// Battlelog asked us to slow down (HTTP 429).
+    #[error("rate limited by Battlelog")]
+    RateLimited {
+        /// Value of the `Retry-After` header, if it was given in seconds.
+        retry_after: Option<Duration>,
+    },
+}
+
+impl Error {
+    /// Whether the request ran into the timeout of the client, see
+    /// [`BattlelogClientBuilder::timeout`](crate::BattlelogClientBuilder::timeout).
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, Error::Transport(err) if err.is_timeout())
+    }
+
+    pub(crate) fn deserialize(err: serde_path_to_error::Error<serde_json::Error>, payload: String) -> Self {
+        Error::Deserialize {
+            path: err.path().to_string(),
+            source: err.into_inner(),
+            payload,
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! Battlefield 4 game modes, maps and factions.
+//!
+//! Keeper only gives out the internal ids (`"RushLarge"`, `"XP0/Levels/XP1_002_Oman/XP0_Oman"`, `1`),
+//! these are the lookup tables for the human-readable names. Unknown values parse into the
+//! `Other` variant, so a new id never fails to parse.
+
+use std::{convert::Infallible, fmt, str::FromStr};
+
+macro_rules! lookup_enum {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $( $variant:ident => ($id:literal $(| $alias:literal)*, $display:literal), )*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $( $variant, )*
+            /// Id that isn't in the lookup table.
+            Other(String),
+        }
+
+        impl $name {
+            /// Every known value, in the order of the lookup table.
+            pub const ALL: &'static [$name] = &[ $( $name::$variant, )* ];
+
+            fn from_id(id: &str) -> Self {
+                match id {
+                    $( $id $(| $alias)* => $name::$variant, )*
+                    other => $name::Other(other.to_string()),
+                }
+            }
+
+            /// The internal id, for example as used in the RCON protocol.
+            pub fn id(&self) -> &str {
+                match self {
+                    $( $name::$variant => $id, )*
+                    $name::Other(id) => id,
+                }
+            }
+
+            /// Human-readable name. Unknown values fall back to the id.
+            pub fn name(&self) -> &str {
+                match self {
+                    $( $name::$variant => $display, )*
+                    $name::Other(id) => id,
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.name())
+            }
+        }
+    };
+}
+
+lookup_enum! {
+    /// # Example
+    /// ```
+    /// use battlelog::GameMode;
+    ///
+    /// let mode: GameMode = "RushLarge".parse().unwrap();
+    /// assert_eq!(GameMode::RushLarge, mode);
+    /// assert_eq!("Rush", mode.name());
+    /// ```
+    pub enum GameMode {
+        ConquestLarge => ("ConquestLarge0" | "ConquestLarge", "Conquest Large"),
+        ConquestSmall => ("ConquestSmall0" | "ConquestSmall", "Conquest"),
+        RushLarge => ("RushLarge0" | "RushLarge", "Rush"),
+        TeamDeathmatch => ("TeamDeathMatch0" | "TeamDeathMatch", "Team Deathmatch"),
+        SquadDeathmatch => ("SquadDeathMatch0" | "SquadDeathMatch", "Squad Deathmatch"),
+        Domination => ("Domination0" | "Domination", "Domination"),
+        Obliteration => ("Obliteration" | "Obliteration0", "Obliteration"),
+        SquadObliteration => ("SquadObliteration0" | "SquadObliteration", "Squad Obliteration"),
+        Defuse => ("Elimination0" | "Elimination", "Defuse"),
+        CaptureTheFlag => ("CaptureTheFlag0" | "CaptureTheFlag", "Capture the Flag"),
+        AirSuperiority => ("AirSuperiority0" | "AirSuperiority", "Air Superiority"),
+        CarrierAssaultLarge => ("CarrierAssaultLarge0" | "CarrierAssaultLarge", "Carrier Assault Large"),
+        CarrierAssaultSmall => ("CarrierAssaultSmall0" | "CarrierAssaultSmall", "Carrier Assault"),
+        ChainLink => ("Chainlink0" | "Chainlink", "Chain Link"),
+        GunMaster => ("GunMaster0" | "GunMaster1" | "GunMaster", "Gun Master"),
+    }
+}
+
+lookup_enum! {
+    /// # Example
+    /// ```
+    /// use battlelog::{Expansion, Map};
+    ///
+    /// let map: Map = "XP0/Levels/XP1_002_Oman/XP0_Oman".parse().unwrap();
+    /// assert_eq!(Map::GulfOfOman, map);
+    /// assert_eq!("Gulf of Oman", map.name());
+    /// assert_eq!(Expansion::SecondAssault, map.expansion());
+    /// ```
+    pub enum Map {
+        // Base game
+        SiegeOfShanghai => ("MP_Siege", "Siege of Shanghai"),
+        ParacelStorm => ("MP_Naval", "Paracel Storm"),
+        OperationLocker => ("MP_Prison", "Operation Locker"),
+        HainanResort => ("MP_Resort", "Hainan Resort"),
+        Dawnbreaker => ("MP_Tremors", "Dawnbreaker"),
+        Zavod311 => ("MP_Abandoned", "Zavod 311"),
+        GolmudRailway => ("MP_Journey", "Golmud Railway"),
+        LancangDam => ("MP_Damage", "Lancang Dam"),
+        FloodZone => ("MP_Flooded", "Flood Zone"),
+        RogueTransmission => ("MP_TheDish", "Rogue Transmission"),
+        // Second Assault
+        CaspianBorder => ("XP0_Caspian", "Caspian Border"),
+        OperationFirestorm => ("XP0_Firestorm", "Operation Firestorm"),
+        OperationMetro => ("XP0_Metro", "Operation Metro"),
+        GulfOfOman => ("XP0_Oman", "Gulf of Oman"),
+        // China Rising
+        SilkRoad => ("XP1_001", "Silk Road"),
+        AltaiRange => ("XP1_002", "Altai Range"),
+        GuilinPeaks => ("XP1_003", "Guilin Peaks"),
+        DragonPass => ("XP1_004", "Dragon Pass"),
+        // Naval Strike
+        LostIslands => ("XP2_001", "Lost Islands"),
+        NanshaStrike => ("XP2_002", "Nansha Strike"),
+        WaveBreaker => ("XP2_003", "Wave Breaker"),
+        OperationMortar => ("XP2_004", "Operation Mortar"),
+        // Dragon's Teeth
+        PearlMarket => ("XP3_MarketPl", "Pearl Market"),
+        Propaganda => ("XP3_Prpganda", "Propaganda"),
+        LumphiniGarden => ("XP3_UrbanGdn", "Lumphini Garden"),
+        SunkenDragon => ("XP3_WtrFront", "Sunken Dragon"),
+        // Final Stand
+        OperationWhiteout => ("XP4_Arctic", "Operation Whiteout"),
+        Hammerhead => ("XP4_SubBase", "Hammerhead"),
+        Hangar21 => ("XP4_Titan", "Hangar 21"),
+        GiantsOfKarelia => ("XP4_WlkrFtry", "Giants of Karelia"),
+        // Community maps
+        ZavodGraveyardShift => ("XP5_Night_01", "Zavod: Graveyard Shift"),
+        OperationOutbreak => ("XP6_CMP", "Operation Outbreak"),
+        DragonValley => ("XP7_Valley", "Dragon Valley"),
+    }
+}
+
+/// Battlefield 4 expansion pack that a [`Map`] belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Expansion {
+    BaseGame,
+    SecondAssault,
+    ChinaRising,
+    NavalStrike,
+    DragonsTeeth,
+    FinalStand,
+    Community,
+    Unknown,
+}
+
+impl Expansion {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Expansion::BaseGame => "Battlefield 4",
+            Expansion::SecondAssault => "Second Assault",
+            Expansion::ChinaRising => "China Rising",
+            Expansion::NavalStrike => "Naval Strike",
+            Expansion::DragonsTeeth => "Dragon's Teeth",
+            Expansion::FinalStand => "Final Stand",
+            Expansion::Community => "Community Operations",
+            Expansion::Unknown => "Unknown",
+        }
+    }
+}
+
+impl fmt::Display for Expansion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Map {
+    pub fn expansion(&self) -> Expansion {
+        let id = self.id();
+        if id.starts_with("MP_") {
+            Expansion::BaseGame
+        } else if id.starts_with("XP0_") {
+            Expansion::SecondAssault
+        } else if id.starts_with("XP1_") {
+            Expansion::ChinaRising
+        } else if id.starts_with("XP2_") {
+            Expansion::NavalStrike
+        } else if id.starts_with("XP3_") {
+            Expansion::DragonsTeeth
+        } else if id.starts_with("XP4_") {
+            Expansion::FinalStand
+        } else if id.starts_with("XP5_") || id.starts_with("XP6_") || id.starts_with("XP7_") {
+            Expansion::Community
+        } else {
+            Expansion::Unknown
+        }
+    }
+}
+
+impl From<&str> for GameMode {
+    fn from(id: &str) -> Self {
+        GameMode::from_id(id)
+    }
+}
+
+impl From<&str> for Map {
+    /// Accepts either the map id (`"MP_Prison"`) or the level path Keeper uses
+    /// (`"Levels/MP_Prison/MP_Prison"`).
+    fn from(path: &str) -> Self {
+        let id = path.rsplit('/').next().unwrap_or(path);
+        Map::from_id(id)
+    }
+}
+
+impl FromStr for GameMode {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(GameMode::from(s))
+    }
+}
+
+impl FromStr for Map {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Map::from(s))
+    }
+}
+
+/// Faction of a team, see [`crate::TeamInfo::get_faction`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Faction {
+    UnitedStates,
+    Russia,
+    China,
+    /// Faction id that isn't in the lookup table.
+    Other(u8),
+}
+
+impl Faction {
+    pub fn id(&self) -> u8 {
+        match self {
+            Faction::UnitedStates => 0,
+            Faction::Russia => 1,
+            Faction::China => 2,
+            Faction::Other(id) => *id,
+        }
+    }
+
+    /// Short name as shown on the scoreboard, for example `"US"`, `"?"` for an unknown id.
+    pub fn short_name(&self) -> &'static str {
+        match self {
+            Faction::UnitedStates => "US",
+            Faction::Russia => "RU",
+            Faction::China => "CN",
+            Faction::Other(_) => "?",
+        }
+    }
+
+    /// `"Unknown"` for an id that isn't in the lookup table, the [`Display`](fmt::Display)
+    /// includes the id.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Faction::UnitedStates => "United States",
+            Faction::Russia => "Russia",
+            Faction::China => "China",
+            Faction::Other(_) => "Unknown",
+        }
+    }
+}
+
+impl From<u8> for Faction {
+    fn from(id: u8) -> Self {
+        match id {
+            0 => Faction::UnitedStates,
+            1 => Faction::Russia,
+            2 => Faction::China,
+            other => Faction::Other(other),
+        }
+    }
+}
+
+impl fmt::Display for Faction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Faction::Other(id) => write!(f, "Faction {}", id),
+            faction => f.write_str(faction.name()),
+        }
+    }
+}
@@ -1,165 +1,394 @@
+pub mod browser;
+pub mod client;
+pub mod error;
+pub mod game;
 pub mod models;
+pub mod server;
 
-use http::{HeaderMap, HeaderValue, StatusCode, header::USER_AGENT};
+pub use browser::{Region, ServerQuery, ServerSummary, UnknownRegion};
+pub use client::{BattlelogClient, BattlelogClientBuilder};
+pub use error::{Error, Result};
+pub use game::*;
 pub use models::*;
+pub use server::{Preset, RotationEntry, ServerInfo, ServerSettings};
 
-pub async fn search_user(soldier_name: &str) -> Result<SearchResult, anyhow::Error> {
-    let params = [("query", soldier_name.to_owned())];
-    let client = reqwest::Client::new();
-    let res = client
-        .post("https://battlelog.battlefield.com/bf4/search/query/")
-        .form(&params)
-        .header(USER_AGENT, "BattleFox")
-        .send()
-        .await?;
-
-    let mut js = res.json::<SearchResponse>().await?;
-    //println!("SearchResponse: {:#?}", js);
-
-    for i in 0..js.data.len() {
-        let result = &js.data[i];
-        //println!("User: {:#?}", result);
-
-        // Requires correct persona name. Apparently default parameters or overrides are not supported so not adding support for partial names now.
-        if result.persona_name.ne(&soldier_name) {
-            //println!("Not a correct persona");
-            continue;
-        }
-
-        if result.namespace != "cem_ea_id" {
-            //println!("Not a PC namespace");
-            continue;
-        }
+// The free functions are kept for compatibility. Each call builds its own client,
+// so prefer a shared `BattlelogClient` when making more than a few requests.
 
-        for val in result.games.values() {
-            if val.parse::<i32>().unwrap() & 2048 == 0 {
-                continue;
-            }
-            //println!("Has BF4");
+pub async fn search_user(soldier_name: &str) -> Result<SearchResult> {
+    BattlelogClient::new().search_user(soldier_name).await
+}
 
-            return Ok(js.data.remove(i))
-        }
-    }
+pub async fn server_snapshot(server_guid: &str) -> Result<KeeperResponse> {
+    BattlelogClient::new().server_snapshot(server_guid).await
+}
 
-    Err(anyhow::anyhow!("User not found"))
+pub async fn server_info(server_guid: &str) -> Result<ServerInfo> {
+    BattlelogClient::new().server_info(server_guid).await
 }
 
-pub async fn server_snapshot(server_guid: &String) -> Result<KeeperResponse, anyhow::Error> {
-    let res = reqwest::Client::new()
-        .get(format!("https://keeper.battlelog.com/snapshot/{}", server_guid))
-        .header(USER_AGENT, "BattleFox")
-        .send()
-        .await?;
+pub async fn ingame_metadata(persona_id: u64) -> Result<IngameMetadataResponse> {
+    BattlelogClient::new().ingame_metadata(persona_id).await
+}
 
-    let status = res.status();
+pub async fn get_user(persona_id: String) -> Result<StatsResponse> {
+    BattlelogClient::new().get_user(&persona_id).await
+}
 
-    let data_str = res
-        .text()
-        .await?;
-    //println!("{}", data_str);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const SERVER_GUID: &str = "4d0151b3-81ff-4268-b4e8-5e60d5bc8765";
+
+    /// Starts a local stand-in for both Battlelog and Keeper.
+    async fn mock_client() -> (MockServer, BattlelogClient) {
+        let server = MockServer::start().await;
+        let client = BattlelogClient::builder()
+            .battlelog_url(server.uri())
+            .keeper_url(server.uri())
+            .build()
+            .unwrap();
+        (server, client)
+    }
 
-    if status != StatusCode::OK {
-        return Err(anyhow::anyhow!(data_str));
+    async fn mount_fixture(server: &MockServer, http_method: &str, url_path: String, fixture: &str) {
+        Mock::given(method(http_method))
+            .and(path(url_path))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(fixture, "application/json"))
+            .mount(server)
+            .await;
     }
 
-    let data: KeeperResponse = serde_json::from_str(&data_str)?;
-    //let data = res.json::<KeeperResponse>().await?;
-    //println!("KeeperResponse: {:#?}", data);
+    #[tokio::test]
+    async fn get_snapshot() {
+        let (server, client) = mock_client().await;
+        mount_fixture(&server, "GET", format!("/snapshot/{}", SERVER_GUID), include_str!("../fixtures/snapshot_rush.json")).await;
 
-    Ok(data)
-}
+        let data = client.server_snapshot(SERVER_GUID).await.unwrap();
+        assert_eq!(18014398528206305, data.snapshot.game_id);
+        assert_eq!(3, data.snapshot.get_players_count());
 
-pub async fn ingame_metadata(persona_id: u64) -> Result<IngameMetadataResponse, anyhow::Error> {
-    let res = reqwest::Client::new()
-        .get(format!("https://battlelog.battlefield.com/api/bf4/pc/persona/1/{}/ingame_metadata", persona_id))
-        .header(USER_AGENT, "BattleFox")
-        .send()
-        .await?;
+        let rush = data.snapshot.rush.as_ref().unwrap();
+        assert_eq!(163, rush.attackers.tickets);
+        assert_eq!(3, rush.defenders.bases_max);
 
-    let status = res.status();
+        let player_by_personaid = data.snapshot.get_player_by_personaid(806262072).unwrap();
+        assert_eq!("xfileFIN", player_by_personaid.name);
 
-    let data_str = res
-        .text()
-        .await?;
-    //println!("{}", data_str);
+        let player_by_name = data.snapshot.get_player_by_name("PocketWolfy").unwrap();
+        assert_eq!("Kiss", player_by_name.tag);
 
-    if status != StatusCode::OK {
-        return Err(anyhow::anyhow!(data_str));
+        assert!(data.snapshot.get_player_by_name("NotInServer").is_none());
     }
 
-    let data: IngameMetadataResponse = serde_json::from_str(&data_str)?;
-    //println!("IngameMetadataResponse: {:#?}", data);
+    #[test]
+    fn snapshot_game_mode_state() {
+        let rush: KeeperResponse = serde_json::from_str(include_str!("../fixtures/snapshot_rush.json")).unwrap();
+        assert!(matches!(rush.snapshot.game_mode_state(), Some(GameModeState::Rush(_))));
+
+        let conquest: KeeperResponse = serde_json::from_str(include_str!("../fixtures/snapshot_conquest.json")).unwrap();
+        match conquest.snapshot.game_mode_state() {
+            Some(GameModeState::Conquest(teams)) => {
+                assert_eq!(412, teams[&1].tickets);
+                assert_eq!(356, teams[&2].tickets);
+                assert_eq!(800, teams[&2].tickets_max);
+            }
+            state => panic!("unexpected state {:?}", state),
+        }
 
-    Ok(data)
-}
+        let mut between_rounds = conquest.snapshot;
+        between_rounds.conquest = None;
+        assert!(between_rounds.game_mode_state().is_none());
+    }
 
-pub async fn get_user(persona_id: String) -> Result<StatsResponse, anyhow::Error> {
-    let mut headers = HeaderMap::new();
-    headers.insert(USER_AGENT, HeaderValue::from_str("BattleFox").unwrap());
-    headers.insert("X-AjaxNavigation", HeaderValue::from_str("1").unwrap());
-    headers.insert(
-        "X-Requested-With",
-        HeaderValue::from_str("XMLHttpRequest").unwrap(),
-    );
+    #[test]
+    fn snapshot_game_modes() {
+        let snapshot = |json: &str| serde_json::from_str::<KeeperResponse>(json).unwrap().snapshot;
 
-    let res = reqwest::Client::new()
-        .get(format!(
-            "https://battlelog.battlefield.com/bf4/soldier/SOLDIER/stats/{}/pc/",
-            persona_id
-        ))
-        .headers(headers)
-        .send()
-        .await?;
+        let tdm = snapshot(include_str!("../fixtures/snapshot_team_deathmatch.json"));
+        assert_eq!(GameMode::TeamDeathmatch, tdm.get_game_mode());
+        match tdm.game_mode_state() {
+            Some(GameModeState::Deathmatch(teams)) => assert_eq!((63, 100), (teams[&1].kills, teams[&1].kills_max)),
+            state => panic!("unexpected state {:?}", state),
+        }
 
-    // let t = res
-    //     .text()
-    //     .await?;
+        let sdm = snapshot(include_str!("../fixtures/snapshot_squad_deathmatch.json"));
+        assert_eq!(GameMode::SquadDeathmatch, sdm.get_game_mode());
+        match sdm.game_mode_state() {
+            Some(GameModeState::SquadDeathmatch(squads)) => {
+                assert_eq!(4, squads.len());
+                assert_eq!(44, squads[&2].kills);
+            }
+            state => panic!("unexpected state {:?}", state),
+        }
+        assert_eq!(5, sdm.team_info.len());
+
+        let carrier = snapshot(include_str!("../fixtures/snapshot_carrier_assault.json"));
+        assert_eq!(GameMode::CarrierAssaultLarge, carrier.get_game_mode());
+        assert_eq!(Map::LostIslands, carrier.get_map());
+        match carrier.game_mode_state() {
+            Some(GameModeState::CarrierAssault(teams)) => {
+                assert_eq!((1, 64), (teams[&1].destroyed_crates, teams[&1].carrier_health));
+            }
+            state => panic!("unexpected state {:?}", state),
+        }
 
-    // // println!("{}", t);
+        let obliteration = snapshot(include_str!("../fixtures/snapshot_obliteration.json"));
+        assert_eq!(GameMode::Obliteration, obliteration.get_game_mode());
+        match obliteration.game_mode_state() {
+            Some(GameModeState::Obliteration(teams)) => assert_eq!((2, 3), (teams[&1].score, teams[&1].score_max)),
+            state => panic!("unexpected state {:?}", state),
+        }
 
-    // let data: StatsResponse = serde_json::from_str(&t).unwrap();
-    // println!("{:#?}", data);
+        let squad_obliteration = snapshot(include_str!("../fixtures/snapshot_squad_obliteration.json"));
+        assert_eq!(GameMode::SquadObliteration, squad_obliteration.get_game_mode());
+        match squad_obliteration.game_mode_state() {
+            Some(GameModeState::SquadObliteration(teams)) => assert_eq!(0, teams[&2].score),
+            state => panic!("unexpected state {:?}", state),
+        }
 
-    let js = res.json::<StatsResponse>().await?;
+        let domination = snapshot(include_str!("../fixtures/snapshot_domination.json"));
+        assert_eq!(GameMode::Domination, domination.get_game_mode());
+        match domination.game_mode_state() {
+            Some(GameModeState::Domination(teams)) => assert_eq!((143, 300), (teams[&2].tickets, teams[&2].tickets_max)),
+            state => panic!("unexpected state {:?}", state),
+        }
 
-    //println!("JS: {:#?}", js);
+        let ctf = snapshot(include_str!("../fixtures/snapshot_capture_the_flag.json"));
+        assert_eq!(GameMode::CaptureTheFlag, ctf.get_game_mode());
+        assert_eq!(Map::Hangar21, ctf.get_map());
+        match ctf.game_mode_state() {
+            Some(GameModeState::CaptureTheFlag(teams)) => assert_eq!((2, 3), (teams[&1].flags, teams[&1].flags_max)),
+            state => panic!("unexpected state {:?}", state),
+        }
 
-    Ok(js)
-}
+        let chainlink = snapshot(include_str!("../fixtures/snapshot_chainlink.json"));
+        assert_eq!(GameMode::ChainLink, chainlink.get_game_mode());
+        match chainlink.game_mode_state() {
+            Some(GameModeState::ChainLink(teams)) => assert_eq!((512, 1000), (teams[&1].tickets, teams[&1].tickets_max)),
+            state => panic!("unexpected state {:?}", state),
+        }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let defuse = snapshot(include_str!("../fixtures/snapshot_defuse.json"));
+        assert_eq!(GameMode::Defuse, defuse.get_game_mode());
+        match defuse.game_mode_state() {
+            Some(GameModeState::Defuse(teams)) => assert_eq!((4, 6), (teams[&2].rounds, teams[&2].rounds_max)),
+            state => panic!("unexpected state {:?}", state),
+        }
+
+        let air = snapshot(include_str!("../fixtures/snapshot_air_superiority.json"));
+        assert_eq!(GameMode::AirSuperiority, air.get_game_mode());
+        assert_eq!(Map::SilkRoad, air.get_map());
+        match air.game_mode_state() {
+            Some(GameModeState::AirSuperiority(teams)) => assert_eq!(96, teams[&2].tickets),
+            state => panic!("unexpected state {:?}", state),
+        }
+    }
+
+    #[test]
+    fn snapshot_names() {
+        let rush: KeeperResponse = serde_json::from_str(include_str!("../fixtures/snapshot_rush.json")).unwrap();
+        assert_eq!(GameMode::RushLarge, rush.snapshot.get_game_mode());
+        assert_eq!(Map::GulfOfOman, rush.snapshot.get_map());
+        assert_eq!("Gulf of Oman", rush.snapshot.get_map().name());
+        assert_eq!(Faction::Russia, rush.snapshot.team_info[&2].get_faction());
+
+        let conquest: KeeperResponse = serde_json::from_str(include_str!("../fixtures/snapshot_conquest.json")).unwrap();
+        assert_eq!("Conquest Large", conquest.snapshot.get_game_mode().name());
+        assert_eq!("Operation Locker", conquest.snapshot.get_map().name());
+        assert_eq!("CN", conquest.snapshot.team_info[&2].get_faction().short_name());
+
+        assert_eq!(GameMode::Other("SquadRush0".to_string()), "SquadRush0".parse().unwrap());
+        assert_eq!("XP9_Unknown", "XP9/Levels/XP9_Unknown/XP9_Unknown".parse::<Map>().unwrap().name());
+        assert_eq!(Faction::Other(7), Faction::from(7));
+        // The ids that aren't in the lookup tables only show up in the Display
+        assert_eq!("EU", Region::Europe.short_name());
+        assert_eq!("Unknown", Faction::from(7).name());
+        assert_eq!("Faction 7", Faction::from(7).to_string());
+        assert_eq!("Region 128", Region::from(128).to_string());
+        assert_eq!("Preset 3", server::Preset::from(3).to_string());
+    }
 
-    // Sadly we can't use asserts, since the player may not be in the server actually.
     #[tokio::test]
-    async fn get_snapshot() {
-        let data = server_snapshot(&"4d0151b3-81ff-4268-b4e8-5e60d5bc8765".to_string()).await.unwrap();
-        println!("{:#?}", data);
+    async fn get_snapshot_not_found() {
+        let (server, client) = mock_client().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+
+        let err = client.server_snapshot(SERVER_GUID).await.unwrap_err();
+        assert!(matches!(err, Error::NotFound(_)), "{:?}", err);
+    }
 
-        let player_by_personaid = data.snapshot.get_player_by_personaid(806262072);
-        if player_by_personaid.is_some() {
-            println!("Found player by personaid: {:#?}", player_by_personaid.unwrap());
+    #[tokio::test]
+    async fn get_snapshot_rate_limited() {
+        let (server, client) = mock_client().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "30"))
+            .mount(&server)
+            .await;
+
+        let err = client.server_snapshot(SERVER_GUID).await.unwrap_err();
+        match err {
+            Error::RateLimited { retry_after } => assert_eq!(Some(Duration::from_secs(30)), retry_after),
+            _ => panic!("unexpected error {:?}", err),
         }
+    }
 
-        let player_by_name = data.snapshot.get_player_by_name("xfileFIN");
-        if player_by_name.is_some() {
-            println!("Found player by name: {:#?}", player_by_name.unwrap());
+    #[tokio::test]
+    async fn get_snapshot_http_error() {
+        let (server, client) = mock_client().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(503).set_body_string("Service Unavailable"))
+            .mount(&server)
+            .await;
+
+        let err = client.server_snapshot(SERVER_GUID).await.unwrap_err();
+        match err {
+            Error::Http { status, body } => {
+                assert_eq!(503, status.as_u16());
+                assert_eq!("Service Unavailable", body);
+            }
+            _ => panic!("unexpected error {:?}", err),
         }
+    }
 
-        // Uncomment to actually display the output of the println!() statements above:
-        panic!();
+    #[tokio::test]
+    async fn get_snapshot_schema_change() {
+        let (server, client) = mock_client().await;
+        let payload = include_str!("../fixtures/snapshot_rush.json").replace("\"faction\": 1", "\"faction\": \"RU\"");
+        mount_fixture(&server, "GET", format!("/snapshot/{}", SERVER_GUID), &payload).await;
+
+        let err = client.server_snapshot(SERVER_GUID).await.unwrap_err();
+        match err {
+            Error::Deserialize { path, payload: raw, .. } => {
+                assert_eq!("snapshot.teamInfo.2.faction", path);
+                assert_eq!(payload, raw);
+            }
+            _ => panic!("unexpected error {:?}", err),
+        }
     }
 
     #[tokio::test]
     async fn get_ingame_metadata() {
-        let meta = ingame_metadata(806262072).await.unwrap();
+        let (server, client) = mock_client().await;
+        mount_fixture(&server, "GET", "/api/bf4/pc/persona/1/806262072/ingame_metadata".to_string(), include_str!("../fixtures/ingame_metadata.json")).await;
+
+        let meta = client.ingame_metadata(806262072).await.unwrap();
         assert_eq!(806262072, meta.persona_id);
+        assert_eq!(
+            Some("https://eaassets-a.akamaihd.net/battlelog/prod/emblems/320/894/2832655391300768492.png".to_string()),
+            meta.get_emblem_url()
+        );
+    }
+
+    #[tokio::test]
+    async fn server_browser() {
+        let (server, client) = mock_client().await;
+        Mock::given(method("GET"))
+            .and(path("/bf4/servers/getServers/pc/"))
+            .and(query_param("q", "battlefox"))
+            .and(query_param("regions", "16"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(include_str!("../fixtures/server_browser.json"), "application/json"))
+            .mount(&server)
+            .await;
+
+        let query = ServerQuery::new().name("battlefox").region(Region::Europe);
+        let servers = client.server_browser(&query).await.unwrap();
+        // The last one is outside of Europe even though the browser returned it
+        assert_eq!(2, servers.len());
+        let locker = &servers[0];
+        assert_eq!("4d0151b3-81ff-4268-b4e8-5e60d5bc8765", locker.guid);
+        assert_eq!(Map::OperationLocker, locker.map);
+        assert_eq!(GameMode::ConquestLarge, locker.game_mode);
+        assert_eq!(Region::Europe, locker.region);
+        assert_eq!((61, 64, 2), (locker.players, locker.max_players, locker.queued));
+
+        let query = query.game_mode(GameMode::RushLarge).game_mode(GameMode::ConquestLarge).min_players(1);
+        let servers = client.server_browser(&query).await.unwrap();
+        assert_eq!(vec!["4d0151b3-81ff-4268-b4e8-5e60d5bc8765"], servers.iter().map(|s| s.guid.as_str()).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn server_browser_page_cap() {
+        // Full pages of servers that never match, the search gives up after the last page
+        let mut page: serde_json::Value = serde_json::from_str(include_str!("../fixtures/server_browser.json")).unwrap();
+        let first = page["data"][0].clone();
+        page["data"] = serde_json::Value::Array(vec![first; browser::BROWSER_PAGE_SIZE]);
+        let (server, client) = mock_client().await;
+        Mock::given(method("GET"))
+            .and(path("/bf4/servers/getServers/pc/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(page))
+            .expect(browser::MAX_BROWSER_PAGES as u64)
+            .mount(&server)
+            .await;
+
+        let servers = client.server_browser(&ServerQuery::new().min_players(100)).await.unwrap();
+        assert!(servers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn get_server_info() {
+        let (server, client) = mock_client().await;
+        mount_fixture(&server, "GET", format!("/bf4/servers/show/pc/{}/", SERVER_GUID), include_str!("../fixtures/server_info.json")).await;
+
+        let info = client.server_info(SERVER_GUID).await.unwrap();
+        assert_eq!("=BFX= BattleFox #1 | Locker 24/7 | 60Hz", info.name);
+        assert!(info.description.starts_with("Welcome"));
+        assert_eq!(Region::Europe, info.region);
+        assert_eq!(Preset::Normal, info.preset);
+        assert!(info.ranked);
+        assert_eq!(60, info.tickrate);
+        assert_eq!(64, info.max_players);
+
+        assert!(!info.settings.vehicles);
+        assert!(info.settings.killcam);
+        assert!(!info.settings.third_person_camera);
+        assert_eq!(Some(100), info.settings.bullet_damage);
+        assert_eq!(Some("0"), info.settings.raw.get("vvsa").map(String::as_str));
+
+        let rotation: Vec<_> = info.rotation.iter().map(|entry| (entry.map.clone(), entry.game_mode.clone())).collect();
+        assert_eq!(
+            vec![
+                (Map::OperationLocker, GameMode::ConquestLarge),
+                (Map::Zavod311, GameMode::ConquestLarge),
+                (Map::OperationMetro, GameMode::RushLarge),
+            ],
+            rotation
+        );
+    }
+
+    #[test]
+    fn browser_names() {
+        let browser: browser::ServerBrowserResponse = serde_json::from_str(include_str!("../fixtures/server_browser.json")).unwrap();
+        let unknown = ServerSummary::from(browser.data.into_iter().nth(2).unwrap());
+        assert_eq!(GameMode::Other("4194304".to_string()), unknown.game_mode);
+        assert!(unknown.has_password);
+        assert_eq!(0, unknown.queued);
+
+        assert_eq!(Some(64), GameMode::ConquestLarge.browser_id());
+        assert_eq!(None, GameMode::Other("Conquest".to_string()).browser_id());
+        assert_eq!(Ok(Region::Oceania), " OC ".parse());
+        assert_eq!(Region::Other(128), Region::from(128));
+        assert!("Mars".parse::<Region>().is_err());
     }
 
-	#[tokio::test]
-	async fn search_user_test() {
-		dbg!(search_user("xfileFIN").await.unwrap());
-		panic!()
-	}
+    #[tokio::test]
+    async fn search_user_test() {
+        let (server, client) = mock_client().await;
+        mount_fixture(&server, "POST", "/bf4/search/query/".to_string(), include_str!("../fixtures/search_user.json")).await;
+
+        // The first result is a console persona, so the PC one should be picked
+        let user = client.search_user("xfileFIN").await.unwrap();
+        assert_eq!(806262072, user.persona_id);
+        assert_eq!("cem_ea_id", user.namespace);
+
+        let err = client.search_user("xfile").await.unwrap_err();
+        assert!(matches!(err, Error::NotFound(_)), "{:?}", err);
+    }
 }
@@ -2,6 +2,8 @@ use serde::{Deserialize, Serialize};
 use serde_aux::prelude::*;
 use std::{collections::HashMap, convert::TryInto};
 
+use crate::game::{Faction, GameMode, Map};
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct User {
@@ -120,6 +122,14 @@ pub struct KeeperResponse {
 ///         conquest: None,
 ///         deathmatch: None,
 ///         carrier_assault: None,
+///         obliteration: None,
+///         squad_obliteration: None,
+///         domination: None,
+///         capture_the_flag: None,
+///         chainlink: None,
+///         squad_deathmatch: None,
+///         defuse: None,
+///         air_superiority: None,
 ///         team_info: {
 ///             2: TeamInfo {
 ///                 faction: 1,
@@ -165,7 +175,14 @@ pub struct Snapshot {
     pub conquest: Option<HashMap<u8, Conquest>>,
     pub deathmatch: Option<HashMap<u8, Deathmatch>>,
     pub carrier_assault: Option<HashMap<u8, CarrierAssault>>,
-    // TODO: Add rest of the game modes
+    pub obliteration: Option<HashMap<u8, Obliteration>>,
+    pub squad_obliteration: Option<HashMap<u8, Obliteration>>,
+    pub domination: Option<HashMap<u8, Domination>>,
+    pub capture_the_flag: Option<HashMap<u8, CaptureTheFlag>>,
+    pub chainlink: Option<HashMap<u8, ChainLink>>,
+    pub squad_deathmatch: Option<HashMap<u8, Deathmatch>>,
+    pub defuse: Option<HashMap<u8, Defuse>>,
+    pub air_superiority: Option<HashMap<u8, AirSuperiority>>,
     pub team_info: HashMap<u8, TeamInfo>,
 }
 
@@ -181,6 +198,35 @@ impl Snapshot {
             .find(|p| p.name == name)
     }
 
+    pub fn get_game_mode(&self) -> GameMode {
+        GameMode::from(self.game_mode.as_str())
+    }
+
+    pub fn get_map(&self) -> Map {
+        Map::from(self.current_map.as_str())
+    }
+
+    /// Returns the state of whichever game mode is being played.
+    ///
+    /// `None` means that the snapshot has no game mode block, which is the case between rounds.
+    pub fn game_mode_state(&self) -> Option<GameModeState<'_>> {
+        let states = [
+            self.rush.as_ref().map(GameModeState::Rush),
+            self.conquest.as_ref().map(GameModeState::Conquest),
+            self.deathmatch.as_ref().map(GameModeState::Deathmatch),
+            self.carrier_assault.as_ref().map(GameModeState::CarrierAssault),
+            self.obliteration.as_ref().map(GameModeState::Obliteration),
+            self.squad_obliteration.as_ref().map(GameModeState::SquadObliteration),
+            self.domination.as_ref().map(GameModeState::Domination),
+            self.capture_the_flag.as_ref().map(GameModeState::CaptureTheFlag),
+            self.chainlink.as_ref().map(GameModeState::ChainLink),
+            self.squad_deathmatch.as_ref().map(GameModeState::SquadDeathmatch),
+            self.defuse.as_ref().map(GameModeState::Defuse),
+            self.air_superiority.as_ref().map(GameModeState::AirSuperiority),
+        ];
+        states.iter().flatten().next().copied()
+    }
+
     pub fn get_players_count(&self) -> u16 {
         let mut player_count :usize = 0;
         for teaminfo in self.team_info.values() {
@@ -236,6 +282,69 @@ pub struct CarrierAssault {
     pub destroyed_crates: u8,
     pub carrier_health: u8
 }
+
+/// Used by both Obliteration and Squad Obliteration, the score is the amount of destroyed objectives.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Obliteration {
+    pub score: u32,
+    pub score_max: u32
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Domination {
+    pub tickets: u32,
+    pub tickets_max: u32
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureTheFlag {
+    pub flags: u8,
+    pub flags_max: u8
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainLink {
+    pub tickets: u32,
+    pub tickets_max: u32
+}
+
+/// Defuse is played in rounds, the first team to win `rounds_max` rounds wins the match.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Defuse {
+    pub rounds: u8,
+    pub rounds_max: u8
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AirSuperiority {
+    pub tickets: u32,
+    pub tickets_max: u32
+}
+
+/// Scoreboard state of the game mode being played, see [`Snapshot::game_mode_state`].
+///
+/// Team based modes are keyed by the team id, same as [`Snapshot::team_info`].
+#[derive(Debug, Clone, Copy)]
+pub enum GameModeState<'a> {
+    Rush(&'a Rush),
+    Conquest(&'a HashMap<u8, Conquest>),
+    Deathmatch(&'a HashMap<u8, Deathmatch>),
+    CarrierAssault(&'a HashMap<u8, CarrierAssault>),
+    Obliteration(&'a HashMap<u8, Obliteration>),
+    SquadObliteration(&'a HashMap<u8, Obliteration>),
+    Domination(&'a HashMap<u8, Domination>),
+    CaptureTheFlag(&'a HashMap<u8, CaptureTheFlag>),
+    ChainLink(&'a HashMap<u8, ChainLink>),
+    SquadDeathmatch(&'a HashMap<u8, Deathmatch>),
+    Defuse(&'a HashMap<u8, Defuse>),
+    AirSuperiority(&'a HashMap<u8, AirSuperiority>),
+}
 //#endregion
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -245,6 +354,12 @@ pub struct TeamInfo {
     pub players: HashMap<u64, Player>
 }
 
+impl TeamInfo {
+    pub fn get_faction(&self) -> Faction {
+        Faction::from(self.faction)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Player {
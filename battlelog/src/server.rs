@@ -0,0 +1,226 @@
+//! Details of a server from its Battlelog page, the things a snapshot doesn't have.
+
+use std::{collections::HashMap, fmt};
+
+use serde::{Deserialize, Serialize};
+use serde_aux::prelude::*;
+
+use crate::browser::Region;
+use crate::game::{GameMode, Map};
+
+/// Game settings preset of a server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Preset {
+    Normal,
+    Hardcore,
+    Infantry,
+    Custom,
+    /// Preset id that isn't in the lookup table.
+    Other(u32),
+}
+
+impl Preset {
+    pub fn id(&self) -> u32 {
+        match self {
+            Preset::Normal => 1,
+            Preset::Hardcore => 2,
+            Preset::Infantry => 4,
+            Preset::Custom => 8,
+            Preset::Other(id) => *id,
+        }
+    }
+
+    /// `"Unknown"` for an id that isn't in the lookup table, the [`Display`](fmt::Display)
+    /// includes the id.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Preset::Normal => "Normal",
+            Preset::Hardcore => "Hardcore",
+            Preset::Infantry => "Infantry Only",
+            Preset::Custom => "Custom",
+            Preset::Other(_) => "Unknown",
+        }
+    }
+}
+
+impl From<u32> for Preset {
+    fn from(id: u32) -> Self {
+        match id {
+            1 => Preset::Normal,
+            2 => Preset::Hardcore,
+            4 => Preset::Infantry,
+            8 => Preset::Custom,
+            other => Preset::Other(other),
+        }
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Preset::Other(id) => write!(f, "Preset {}", id),
+            preset => f.write_str(preset.name()),
+        }
+    }
+}
+
+/// The game settings that are commonly looked at. Every setting is in `raw`, keyed by the
+/// short names Battlelog uses, for example `vvsa` for the vehicles.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ServerSettings {
+    pub vehicles: bool,
+    pub killcam: bool,
+    pub third_person_camera: bool,
+    pub minimap: bool,
+    pub hud: bool,
+    pub regenerative_health: bool,
+    /// Bullet damage in percent
+    pub bullet_damage: Option<u32>,
+    /// Soldier health in percent
+    pub soldier_health: Option<u32>,
+    pub raw: HashMap<String, String>,
+}
+
+impl ServerSettings {
+    fn from_raw(raw: HashMap<String, String>) -> Self {
+        // Battlelog leaves out the switches that are in their default state, which is on
+        let flag = |key: &str| raw.get(key).is_none_or(|value| value.trim() != "0");
+        let number = |key: &str| raw.get(key).and_then(|value| value.trim().parse().ok());
+
+        ServerSettings {
+            vehicles: flag("vvsa"),
+            killcam: flag("vkca"),
+            third_person_camera: flag("v3ca"),
+            minimap: flag("vmin"),
+            hud: flag("vhud"),
+            regenerative_health: flag("vrhe"),
+            bullet_damage: number("vbdm"),
+            soldier_health: number("vshe"),
+            raw,
+        }
+    }
+}
+
+/// A map of the rotation, with the game mode it's played in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RotationEntry {
+    pub map: Map,
+    pub game_mode: GameMode,
+}
+
+/// Details of a server, see [`BattlelogClient::server_info`](crate::BattlelogClient::server_info).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerInfo {
+    pub guid: String,
+    pub name: String,
+    pub description: String,
+    pub region: Region,
+    /// Two letter country code of the server location
+    pub country: String,
+    pub preset: Preset,
+    pub ranked: bool,
+    /// Simulation rate in Hz, 30 unless the server runs a high tickrate
+    pub tickrate: u32,
+    pub max_players: u16,
+    pub settings: ServerSettings,
+    /// The maps in the order they're played
+    pub rotation: Vec<RotationEntry>,
+}
+
+impl From<ServerPage> for ServerInfo {
+    fn from(server: ServerPage) -> Self {
+        ServerInfo {
+            region: Region::from(server.region),
+            preset: Preset::from(server.preset),
+            ranked: server.ranked,
+            tickrate: server.tick_rate.unwrap_or(DEFAULT_TICKRATE),
+            max_players: server.max_players,
+            settings: ServerSettings::from_raw(server.settings),
+            rotation: server
+                .maps
+                .maps
+                .iter()
+                .map(|entry| RotationEntry {
+                    map: Map::from(entry.map.as_str()),
+                    game_mode: GameMode::from_browser_id(entry.map_mode),
+                })
+                .collect(),
+            guid: server.guid,
+            name: server.name,
+            description: server.description,
+            country: server.country,
+        }
+    }
+}
+
+const DEFAULT_TICKRATE: u32 = 30;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerPageResponse {
+    pub template: String,
+    pub context: ServerPageContext,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerPageContext {
+    pub server: ServerPage,
+}
+
+/// A server as its Battlelog page has it, see [`ServerInfo`] for the typed version.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerPage {
+    pub guid: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub region: u32,
+    #[serde(default)]
+    pub country: String,
+    pub preset: u32,
+    #[serde(default)]
+    pub ranked: bool,
+    pub tick_rate: Option<u32>,
+    pub max_players: u16,
+    /// The values are numbers as strings, `"1"` and `"0"` for the switches
+    #[serde(default, deserialize_with = "deserialize_settings")]
+    pub settings: HashMap<String, String>,
+    #[serde(default)]
+    pub maps: ServerPageMaps,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerPageMaps {
+    pub maps: Vec<ServerPageMap>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerPageMap {
+    /// Map id, for example `"MP_Prison"`
+    pub map: String,
+    /// Game mode id of the browser, see [`GameMode::from_browser_id`]
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub map_mode: u32,
+}
+
+/// Battlelog mixes strings and numbers in the settings.
+fn deserialize_settings<'de, D>(deserializer: D) -> Result<HashMap<String, String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let values: HashMap<String, serde_json::Value> = HashMap::deserialize(deserializer)?;
+    Ok(values
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                serde_json::Value::String(value) => value,
+                other => other.to_string(),
+            };
+            (key, value)
+        })
+        .collect())
+}
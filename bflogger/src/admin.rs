@@ -0,0 +1,208 @@
+//! Admin HTTP API to change the polled servers without a restart.
+//!
+//! - `GET /servers` lists the running servers
+//! - `PUT /servers/{guid}` starts or updates a server, the body is the JSON version of its
+//!   `[[servers]]` entry and may be empty to use the defaults
+//! - `DELETE /servers/{guid}` stops a server
+//! - `POST /reload` reloads the servers of the config file
+//!
+//! Every answer is JSON. If a token is configured it's required as `Authorization: Bearer <token>`.
+
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use hyper::{
+    body,
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::config::AdminConfig;
+use crate::servers::ServerPool;
+
+/// Everything the handlers need.
+struct Admin {
+    pool: Arc<ServerPool>,
+    token: Option<String>,
+    /// The config file that `/reload` reads, `None` without one
+    config_file: Option<PathBuf>,
+}
+
+/// Serves the API until the process exits.
+pub async fn serve(addr: SocketAddr, config: &AdminConfig, pool: Arc<ServerPool>, config_file: Option<PathBuf>) {
+    let admin = Arc::new(Admin {
+        pool,
+        token: config.token.clone(),
+        config_file,
+    });
+    let make_service = make_service_fn(move |_| {
+        let admin = admin.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let admin = admin.clone();
+                async move { Ok::<_, Infallible>(handle(request, &admin).await) }
+            }))
+        }
+    });
+
+    let server = match Server::try_bind(&addr) {
+        Ok(builder) => builder.serve(make_service),
+        Err(err) => return eprintln!("Failed to listen for the admin API on {}: {}", addr, err),
+    };
+    eprintln!("Serving the admin API on http://{}", addr);
+    if let Err(err) = server.await {
+        eprintln!("Admin server failed: {}", err);
+    }
+}
+
+async fn handle(request: Request<Body>, admin: &Admin) -> Response<Body> {
+    if let Some(token) = &admin.token {
+        let expected = format!("Bearer {}", token);
+        let authorization = request.headers().get(AUTHORIZATION).and_then(|value| value.to_str().ok());
+        if authorization != Some(expected.as_str()) {
+            return error(StatusCode::UNAUTHORIZED, "missing or wrong bearer token");
+        }
+    }
+
+    let path = request.uri().path().trim_end_matches('/').to_string();
+    let guid = path.strip_prefix("/servers/").filter(|guid| !guid.is_empty() && !guid.contains('/'));
+
+    match (request.method(), path.as_str(), guid) {
+        (&Method::GET, "/servers", _) => respond(StatusCode::OK, &admin.pool.servers()),
+        (&Method::POST, "/reload", _) => match admin.pool.reload(admin.config_file.as_deref()) {
+            Ok(changes) => {
+                eprintln!("Reloaded the servers over the admin API: {}", changes);
+                respond(StatusCode::OK, &changes)
+            }
+            Err(err) => error(StatusCode::UNPROCESSABLE_ENTITY, &err),
+        },
+        (&Method::PUT, _, Some(guid)) => {
+            let guid = guid.to_string();
+            let json = match body::to_bytes(request.into_body()).await {
+                Ok(json) => json,
+                Err(err) => return error(StatusCode::BAD_REQUEST, &err.to_string()),
+            };
+            match admin.pool.add(&guid, &json) {
+                Ok(changes) => {
+                    eprintln!("Changed the servers over the admin API: {}", changes);
+                    respond(StatusCode::OK, &changes)
+                }
+                Err(err) => error(StatusCode::UNPROCESSABLE_ENTITY, &err),
+            }
+        }
+        (&Method::DELETE, _, Some(guid)) => match admin.pool.remove(guid) {
+            Some(changes) => {
+                eprintln!("Changed the servers over the admin API: {}", changes);
+                respond(StatusCode::OK, &changes)
+            }
+            None => error(StatusCode::NOT_FOUND, &format!("server {} isn't running", guid)),
+        },
+        _ => error(StatusCode::NOT_FOUND, "not found, try /servers or /reload"),
+    }
+}
+
+fn respond<T: Serialize>(status: StatusCode, value: &T) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(value).expect("Failed to serialize to JSON")))
+        .expect("valid response")
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Body> {
+    respond(status, &json!({ "error": message }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    use battlelog::BattlelogClient;
+    use serde_json::Value;
+
+    use crate::config::{Config, PollingConfig, QueueConfig};
+    use crate::metrics::Metrics;
+    use crate::poll::RateLimit;
+    use crate::servers::Context;
+    use crate::sinks::{SinkQueue, StdoutSink};
+
+    const GUID: &str = "4d0151b3-81ff-4268-b4e8-5e60d5bc8765";
+
+    fn admin(token: Option<&str>) -> Admin {
+        let config = Config::parse("[admin]\nlisten = \"127.0.0.1:0\"\n[sinks.console]\ntype = \"stdout\"\n", false, |_| None).unwrap();
+        let metrics = Arc::new(Metrics::default());
+        let mut queues = BTreeMap::new();
+        let queue = SinkQueue::start("console", Arc::new(StdoutSink), &QueueConfig::default(), metrics.clone()).unwrap();
+        queues.insert("console".to_string(), queue);
+        let context = Context {
+            battlelog: BattlelogClient::builder().keeper_url("http://127.0.0.1:9").build().unwrap(),
+            metrics,
+            queues,
+            rate_limit: Arc::new(RateLimit::default()),
+            polling: PollingConfig::default(),
+        };
+
+        Admin {
+            pool: ServerPool::start(context, config),
+            token: token.map(str::to_string),
+            config_file: None,
+        }
+    }
+
+    async fn call(admin: &Admin, method: Method, uri: &str, body: &str, token: Option<&str>) -> (StatusCode, Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let response = handle(request.body(Body::from(body.to_string())).unwrap(), admin).await;
+        let status = response.status();
+        let body = body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn servers() {
+        let admin = admin(None);
+
+        let (status, body) = call(&admin, Method::PUT, &format!("/servers/{}", GUID), r#"{"label": "Added"}"#, None).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!([GUID]), body["started"]);
+
+        let (status, body) = call(&admin, Method::GET, "/servers", "", None).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("Added", body[0]["label"]);
+        assert_eq!("admin", body[0]["source"]);
+
+        let (status, body) = call(&admin, Method::PUT, "/servers/not-a-guid", "", None).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+        assert!(body["error"].as_str().unwrap().contains("not a server guid"), "{}", body);
+
+        let (status, _) = call(&admin, Method::DELETE, &format!("/servers/{}", GUID), "", None).await;
+        assert_eq!(StatusCode::OK, status);
+        let (status, _) = call(&admin, Method::DELETE, &format!("/servers/{}", GUID), "", None).await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+
+        admin.pool.stop_all().await;
+    }
+
+    #[tokio::test]
+    async fn token() {
+        let admin = admin(Some("secret"));
+
+        let (status, _) = call(&admin, Method::GET, "/servers", "", None).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, status);
+        let (status, _) = call(&admin, Method::GET, "/servers", "", Some("wrong")).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, status);
+        let (status, body) = call(&admin, Method::GET, "/servers", "", Some("secret")).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!([]), body);
+    }
+}
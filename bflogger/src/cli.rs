@@ -0,0 +1,461 @@
+use std::path::PathBuf;
+
+use battlelog::{BattlelogClient, GameMode, Map, ServerQuery, Snapshot};
+use chrono::{Duration, Utc};
+use clap::{Parser, Subcommand};
+
+use crate::config::{Config, SinkConfig};
+use crate::rotation::RotationReport;
+use crate::sinks::SqliteSink;
+
+/// Logs Battlefield 4 server snapshots from Battlelog into a time-series database.
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Cli {
+    /// Config file to read, defaults to bflogger.toml if it exists
+    #[arg(short, long, global = true, env = "CONFIG_FILE")]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Start logging the configured servers (the default)
+    Run,
+    /// Print the current snapshot of a server
+    Snapshot {
+        /// Server guid, for example 4d0151b3-81ff-4268-b4e8-5e60d5bc8765
+        guid: String,
+        /// Print the raw snapshot as JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the name, settings and map rotation of a server from its Battlelog page
+    Info {
+        /// Server guid, for example 4d0151b3-81ff-4268-b4e8-5e60d5bc8765
+        guid: String,
+    },
+    /// Search the Battlelog server browser, for finding the server guids
+    Browse {
+        /// Part of the server name, for example a clan tag
+        name: Option<String>,
+        /// Region like eu or "north america", can be given several times
+        #[arg(long)]
+        region: Vec<String>,
+        /// Game mode id like ConquestLarge0, can be given several times
+        #[arg(long)]
+        mode: Vec<String>,
+        /// Map id like MP_Prison, can be given several times
+        #[arg(long)]
+        map: Vec<String>,
+        #[arg(long)]
+        min_players: Option<u16>,
+        #[arg(long)]
+        max_players: Option<u16>,
+        #[arg(long, default_value_t = 60)]
+        limit: usize,
+    },
+    /// Look up a PC persona by the exact soldier name
+    Search {
+        name: String,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the in-game metadata (club, country, emblem) of a persona
+    Player {
+        persona_id: u64,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Validate the config file and environment variables and print the result
+    CheckConfig,
+    /// Query the history kept in a SQLite sink
+    Query {
+        /// Name of the SQLite sink, needed if there are several
+        #[arg(long)]
+        sink: Option<String>,
+        #[command(subcommand)]
+        query: Query,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Query {
+    /// Average and peak player count of a server over time
+    Players {
+        guid: String,
+        /// How far back to look
+        #[arg(long, default_value_t = 24)]
+        hours: u32,
+        /// Length of a time bucket
+        #[arg(long, default_value_t = 60)]
+        bucket_minutes: u32,
+    },
+    /// The last rounds of a server
+    Rounds {
+        guid: String,
+        #[arg(long, default_value_t = 20)]
+        limit: u32,
+    },
+    /// Score, kills, deaths and play time per player over the finished sessions
+    PlayerTotals {
+        /// Only count the sessions on this server
+        #[arg(long)]
+        guid: Option<String>,
+        #[arg(long, default_value_t = 20)]
+        limit: u32,
+    },
+    /// Which maps of the rotation get skipped or end early, and their average player count.
+    /// Only the SQLite sink keeps what this needs, and only the rounds since the server switched
+    /// to its current rotation count.
+    Rotation {
+        guid: String,
+        /// How far back to look
+        #[arg(long, default_value_t = 7)]
+        days: u32,
+        /// Rounds that got less far than this, from 0 to 1, count as ended early
+        #[arg(long, default_value_t = 0.9)]
+        min_progress: f64,
+    },
+}
+
+pub async fn snapshot(client: &BattlelogClient, guid: &str, json: bool) -> Result<(), battlelog::Error> {
+    let data = client.server_snapshot(guid).await?;
+
+    if json {
+        println!("{}", to_json(&data.snapshot));
+    } else {
+        print_snapshot(&data.snapshot);
+    }
+    Ok(())
+}
+
+pub async fn info(client: &BattlelogClient, guid: &str) -> Result<(), battlelog::Error> {
+    let info = client.server_info(guid).await?;
+    let on_off = |on: bool| if on { "on" } else { "off" };
+
+    println!("Name:       {}", info.name);
+    println!("Guid:       {}", info.guid);
+    println!("Region:     {} ({})", info.region, info.country);
+    println!("Preset:     {}{}", info.preset, if info.ranked { "" } else { ", unranked" });
+    println!("Tickrate:   {} Hz", info.tickrate);
+    println!("Slots:      {}", info.max_players);
+    println!(
+        "Settings:   vehicles {}, killcam {}, 3p cam {}, minimap {}, hud {}",
+        on_off(info.settings.vehicles),
+        on_off(info.settings.killcam),
+        on_off(info.settings.third_person_camera),
+        on_off(info.settings.minimap),
+        on_off(info.settings.hud)
+    );
+    if !info.description.is_empty() {
+        println!();
+        println!("{}", info.description.trim());
+    }
+    println!();
+    println!("Rotation:");
+    for (i, entry) in info.rotation.iter().enumerate() {
+        println!("  {:>2}. {:<24} {}", i + 1, entry.map.name(), entry.game_mode.name());
+    }
+    Ok(())
+}
+
+pub async fn browse(client: &BattlelogClient, query: &ServerQuery) -> Result<(), battlelog::Error> {
+    let servers = client.server_browser(query).await?;
+
+    println!(
+        "{:<36} {:<40} {:<4} {:<20} {:<16} {:>9}",
+        "Guid", "Name", "Reg", "Map", "Mode", "Players"
+    );
+    for server in servers {
+        let name: String = server.name.chars().take(40).collect();
+        println!(
+            "{:<36} {:<40} {:<4} {:<20} {:<16} {:>4}/{:<4}",
+            server.guid,
+            name,
+            server.region.short_name(),
+            server.map.name(),
+            server.game_mode.name(),
+            server.players,
+            server.max_players
+        );
+    }
+    Ok(())
+}
+
+pub async fn search(client: &BattlelogClient, name: &str, json: bool) -> Result<(), battlelog::Error> {
+    let user = client.search_user(name).await?;
+
+    if json {
+        println!("{}", to_json(&user));
+    } else {
+        println!("Persona:    {} ({})", user.persona_name, user.persona_id);
+        println!("User:       {} ({})", user.user.username.as_deref().unwrap_or("-"), user.user_id);
+        println!("Namespace:  {}", user.namespace);
+    }
+    Ok(())
+}
+
+pub async fn player(client: &BattlelogClient, persona_id: u64, json: bool) -> Result<(), battlelog::Error> {
+    let meta = client.ingame_metadata(persona_id).await?;
+
+    if json {
+        println!("{}", to_json(&meta));
+    } else {
+        println!("Persona id: {}", meta.persona_id);
+        println!("Club:       {} {}", meta.club_name, meta.club_rank);
+        println!("Country:    {}", meta.country_code);
+        println!("Emblem:     {}", meta.get_emblem_url().as_deref().unwrap_or("-"));
+    }
+    Ok(())
+}
+
+pub fn check_config(config: &Config) {
+    println!("Config is valid.");
+    println!();
+    if let Some(addr) = config.metrics.listen {
+        println!("Metrics: http://{}/metrics", addr);
+        println!();
+    }
+    if let Some(discovery) = &config.discovery {
+        let mut filters = Vec::new();
+        if let Some(name) = &discovery.name {
+            filters.push(format!("name contains {:?}", name));
+        }
+        for (label, values) in &[("regions", &discovery.regions), ("modes", &discovery.game_modes), ("maps", &discovery.maps)] {
+            if !values.is_empty() {
+                filters.push(format!("{} {}", label, values.join(", ")));
+            }
+        }
+        println!(
+            "Discovery: up to {} servers where {}, searched every {} s",
+            discovery.limit,
+            filters.join(" and "),
+            discovery.interval / 1000
+        );
+        println!();
+    }
+    if let Some(addr) = config.admin.listen {
+        let auth = if config.admin.token.is_some() { "bearer token" } else { "no token" };
+        println!("Admin API: http://{}/servers ({})", addr, auth);
+        println!();
+    }
+    println!("Sinks:");
+    for (name, sink) in &config.sinks {
+        match sink {
+            SinkConfig::Influxdb(influxdb) if influxdb.is_v2() => println!(
+                "  {:<16} influxdb 2.x {} (org {}, bucket {})",
+                name,
+                influxdb.url,
+                influxdb.org.as_deref().unwrap_or(""),
+                influxdb.bucket.as_deref().unwrap_or("")
+            ),
+            SinkConfig::Influxdb(influxdb) => println!(
+                "  {:<16} influxdb 1.x {} (database {}{})",
+                name,
+                influxdb.url,
+                influxdb.database.as_deref().unwrap_or(""),
+                influxdb.username.as_deref().map(|username| format!(", user {}", username)).unwrap_or_default()
+            ),
+            SinkConfig::Json { path } => println!("  {:<16} json {}", name, path.display()),
+            SinkConfig::Csv { directory } => println!("  {:<16} csv {}", name, directory.display()),
+            SinkConfig::Stdout => println!("  {:<16} stdout", name),
+            SinkConfig::Sqlite { path, retention_days } => match retention_days {
+                Some(days) => println!("  {:<16} sqlite {} (keeps {} days)", name, path.display(), days),
+                None => println!("  {:<16} sqlite {}", name, path.display()),
+            },
+            SinkConfig::Postgres { timescaledb, .. } => {
+                println!("  {:<16} postgres{}", name, if *timescaledb { " (timescaledb)" } else { "" })
+            }
+        }
+    }
+    println!();
+    match &config.queue.spool_directory {
+        Some(directory) => println!(
+            "Queue: up to {} points per sink in memory, the rest spooled to {}",
+            config.queue.max_points,
+            directory.display()
+        ),
+        None => println!("Queue: up to {} points per sink in memory", config.queue.max_points),
+    }
+    println!();
+    println!("Servers:");
+    for server in &config.servers {
+        let mut measurements: Vec<String> = server.measurements
+            .iter()
+            .map(|measurement| format!("{:?}", measurement).to_lowercase())
+            .collect();
+        measurements.sort();
+
+        println!("  {} {}", server.guid, server.label.as_deref().unwrap_or(""));
+        println!("    interval:     {} ms", server.interval);
+        println!("    measurements: {}", measurements.join(", "));
+        println!("    sinks:        {}", server.sinks.join(", "));
+        if !server.tags.is_empty() {
+            let tags: Vec<String> = server.tags.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+            println!("    tags:         {}", tags.join(", "));
+        }
+    }
+}
+
+pub fn query(config: &Config, sink: Option<&str>, query: Query) -> Result<(), String> {
+    let mut sqlite = config.sinks.iter().filter_map(|(name, sink)| match sink {
+        SinkConfig::Sqlite { path, .. } => Some((name, path)),
+        _ => None,
+    });
+    let path = match sink {
+        Some(sink) => sqlite
+            .find(|(name, _)| name.as_str() == sink)
+            .map(|(_, path)| path)
+            .ok_or_else(|| format!("there is no SQLite sink named {:?}", sink))?,
+        None => match (sqlite.next(), sqlite.next()) {
+            (Some((_, path)), None) => path,
+            (None, _) => return Err("there is no SQLite sink in the config".to_string()),
+            (Some(_), Some(_)) => return Err("there are several SQLite sinks, pick one with --sink".to_string()),
+        },
+    };
+    let db = SqliteSink::open(path, None).map_err(|err| err.to_string())?;
+
+    match query {
+        Query::Players { guid, hours, bucket_minutes } => {
+            let since = Utc::now() - Duration::hours(i64::from(hours));
+            let counts = db.player_count(&guid, since, bucket_minutes * 60).map_err(|err| err.to_string())?;
+
+            println!("{:<20} {:>8} {:>5}", "Time", "Average", "Peak");
+            for count in counts {
+                println!("{:<20} {:>8.1} {:>5}", count.time.format("%Y-%m-%d %H:%M"), count.average, count.peak);
+            }
+        }
+        Query::Rounds { guid, limit } => {
+            let rounds = db.round_history(&guid, limit).map_err(|err| err.to_string())?;
+
+            println!(
+                "{:<17} {:<24} {:<20} {:>6} {:>6} {:>5}  Scores",
+                "Ended", "Map", "Mode", "Length", "Winner", "Peak"
+            );
+            for round in rounds {
+                println!(
+                    "{:<17} {:<24} {:<20} {:>3}:{:02} {:>6} {:>5}  {}",
+                    round.ended_at.format("%Y-%m-%d %H:%M"),
+                    Map::from(round.current_map.as_str()).name(),
+                    GameMode::from(round.game_mode.as_str()).name(),
+                    round.duration / 60,
+                    round.duration % 60,
+                    round.winner.map(|team| team.to_string()).unwrap_or_else(|| "-".to_string()),
+                    round.peak_players,
+                    round.scores
+                );
+            }
+        }
+        Query::PlayerTotals { guid, limit } => {
+            let totals = db.player_totals(guid.as_deref(), limit).map_err(|err| err.to_string())?;
+
+            println!(
+                "{:<24} {:>12} {:>8} {:>8} {:>8} {:>8} {:>8}",
+                "Name", "Persona id", "Sessions", "Hours", "Score", "Kills", "Deaths"
+            );
+            for player in totals {
+                println!(
+                    "{:<24} {:>12} {:>8} {:>8.1} {:>8} {:>8} {:>8}",
+                    player.name,
+                    player.persona_id,
+                    player.sessions,
+                    player.play_time as f64 / 3600.0,
+                    player.score,
+                    player.kills,
+                    player.deaths
+                );
+            }
+        }
+        Query::Rotation { guid, days, min_progress } => {
+            let rotation = db.rotation(&guid).map_err(|err| err.to_string())?;
+            let changed = match rotation.first() {
+                Some(entry) => entry.time,
+                None => {
+                    return Err(format!(
+                        "no rotation of server {} is recorded, it's written with the rotation measurement once the server details are fetched",
+                        guid
+                    ))
+                }
+            };
+            // The rounds before the switch to this rotation were played with another one
+            let since = (Utc::now() - Duration::days(i64::from(days))).max(changed);
+            let rounds = db.rounds_since(&guid, since).map_err(|err| err.to_string())?;
+            let population = db.map_population(&guid, since).map_err(|err| err.to_string())?;
+            let report = RotationReport::new(&rotation, &rounds, &population, min_progress);
+            let outside = report.maps.iter().any(|map| map.position.is_none());
+
+            println!(
+                "{:>3}  {:<24} {:<20} {:>6} {:>7} {:>5} {:>8}",
+                "#", "Map", "Mode", "Played", "Skipped", "Early", "Players"
+            );
+            for map in report.maps {
+                println!(
+                    "{:>3}  {:<24} {:<20} {:>6} {:>7} {:>5} {:>8}",
+                    map.position.map(|position| (position + 1).to_string()).unwrap_or_else(|| "-".to_string()),
+                    map.current_map.name(),
+                    map.game_mode.name(),
+                    map.played,
+                    map.skipped,
+                    map.ended_early,
+                    map.average_players.map(|average| format!("{:.1}", average)).unwrap_or_else(|| "-".to_string())
+                );
+            }
+            println!();
+            if since == changed {
+                println!("{} rounds since the rotation changed on {}", rounds.len(), changed.format("%Y-%m-%d %H:%M"));
+            } else {
+                println!("{} rounds in the last {} days", rounds.len(), days);
+            }
+            if outside {
+                println!("Maps without a place were played outside of the rotation");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_string_pretty(value).expect("Failed to serialize to JSON")
+}
+
+fn print_snapshot(snapshot: &Snapshot) {
+    println!("Map:        {} ({})", snapshot.get_map().name(), snapshot.current_map);
+    println!("Mode:       {} ({})", snapshot.get_game_mode().name(), snapshot.game_mode);
+    println!("Game id:    {}", snapshot.game_id);
+    println!("Round time: {}:{:02}", snapshot.round_time / 60, snapshot.round_time % 60);
+    println!(
+        "Players:    {}/{} ({} waiting)",
+        snapshot.get_players_count(),
+        snapshot.max_players,
+        snapshot.waiting_players
+    );
+    println!("Running:    {}", snapshot.game_mode_state().is_some());
+
+    let mut teams: Vec<_> = snapshot.team_info.iter().collect();
+    teams.sort_by_key(|(team, _)| **team);
+
+    for (team, team_info) in teams {
+        if team_info.players.is_empty() {
+            continue;
+        }
+
+        println!();
+        println!("Team {} - {}", team, team_info.get_faction());
+        println!(
+            "  {:<8} {:<24} {:>6} {:>6} {:>6} {:>5} {:>5}",
+            "Tag", "Name", "Score", "Kills", "Deaths", "Squad", "Rank"
+        );
+
+        let mut players: Vec<_> = team_info.players.values().collect();
+        players.sort_by_key(|player| std::cmp::Reverse(player.score));
+        for player in players {
+            println!(
+                "  {:<8} {:<24} {:>6} {:>6} {:>6} {:>5} {:>5}",
+                player.tag, player.name, player.score, player.kills, player.deaths, player.squad, player.rank
+            );
+        }
+    }
+}
@@ -1,156 +1,169 @@
-use std::time::Duration;
-
-use battlelog::server_snapshot;
-use chrono::{DateTime, Utc};
+mod admin;
+mod cli;
+mod config;
+mod metrics;
+mod poll;
+mod records;
+mod rotation;
+mod round;
+mod servers;
+mod session;
+mod sinks;
+mod supervisor;
+
+use std::{collections::BTreeMap, path::Path, sync::Arc, time::Duration};
+
+use battlelog::BattlelogClient;
+use clap::Parser;
+use cli::{Cli, Command};
 use dotenv::dotenv;
-use influxdb::Client;
-use influxdb::InfluxDbWriteable;
-use tokio::time::sleep;
-
-#[derive(InfluxDbWriteable)]
-struct SnapshotReading {
-    time: DateTime<Utc>,
-    #[influxdb(tag)]
-    server_guid: String,
-    game_id: u64,
-    #[influxdb(tag)]
-    game_mode: String,
-    map_variant: u8,
-    #[influxdb(tag)]
-    current_map: String,
-    current_map_name: String,
-    max_players: u8,
-    waiting_players: u8,
-    players: u16,
-    round_time: u32,
-    default_round_time_multiplier: u32,
-
-    #[influxdb(tag)]
-    round_running: bool,
-
-    round_running_val: bool,
-
-    defender_team: Option<u8>,
-    defender_bases: Option<u8>,
-    defender_bases_max: Option<u8>,
-    defender_attacker: Option<u8>,
-
-    attacker_team: Option<u8>,
-    attacker_tickets: Option<u16>,
-    attacker_tickets_max: Option<u16>,
-    attacker_attacker: Option<u8>,
-}
-
-async fn log_new_entry(client: &Client, server_guid: &String) {
-    println!("Logging new entry for server guid {}", &server_guid);
-
-    match server_snapshot(&server_guid).await {
-        Ok(data) => {
-            // Let's write some data into a measurement called `snapshot`
-            let mut snapshot_reading = SnapshotReading {
-                time: Utc::now(),
-                server_guid: server_guid.to_string(),
-                game_id: data.snapshot.game_id,
-                game_mode: data.snapshot.game_mode.to_string(),
-                map_variant: data.snapshot.map_variant,
-                current_map: data
-                    .snapshot
-                    .current_map
-                    .split('/')
-                    .last()
-                    .unwrap_or("")
-                    .to_string(),
-                current_map_name: data
-                    .snapshot
-                    .current_map
-                    .split('/')
-                    .last()
-                    .unwrap_or("")
-                    .to_string(),
-                max_players: data.snapshot.max_players,
-                waiting_players: data.snapshot.waiting_players,
-                players: data.snapshot.get_players_count(),
-                round_time: data.snapshot.round_time,
-                default_round_time_multiplier: data.snapshot.default_round_time_multiplier,
-
-                round_running: data.snapshot.rush.is_some(),
-
-                round_running_val: data.snapshot.rush.is_some(),
-
-                defender_team: None,
-                defender_bases: None,
-                defender_bases_max: None,
-                defender_attacker: None,
-
-                attacker_team: None,
-                attacker_tickets: None,
-                attacker_tickets_max: None,
-                attacker_attacker: None,
-            };
-
-            if data.snapshot.rush.is_some() {
-                let rush = data.snapshot.rush.unwrap();
-                let defenders = rush.defenders;
+use config::{BattlelogConfig, Config, DiscoveryConfig};
+use metrics::Metrics;
+use poll::RateLimit;
+use servers::{Context, ServerPool};
+use sinks::SinkQueue;
 
-                snapshot_reading.defender_team = Some(defenders.team);
-                snapshot_reading.defender_bases = Some(defenders.bases);
-                snapshot_reading.defender_bases_max = Some(defenders.bases_max);
-                snapshot_reading.defender_attacker = Some(defenders.attacker);
+/// How long the sinks get to write their queues on shutdown, `docker stop` kills after 10 s.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(8);
 
-                let attackers = rush.attackers;
-                snapshot_reading.attacker_team = Some(attackers.team);
-                snapshot_reading.attacker_tickets = Some(attackers.tickets);
-                snapshot_reading.attacker_tickets_max = Some(attackers.tickets_max);
-                snapshot_reading.attacker_attacker = Some(attackers.attacker);
-            }
+#[tokio::main]
+async fn main() {
+    dotenv().ok();
 
-            let write_result = client.query(&snapshot_reading.into_query("snapshot")).await;
-            if let Err(err) = write_result {
-                eprintln!("Error writing to db: {}", err)
+    let cli = Cli::parse();
+    let config_path = cli.config.as_deref();
+
+    let result = match cli.command.unwrap_or(Command::Run) {
+        Command::Run => match Config::load(config_path) {
+            Ok(config) => run(config, config_path).await,
+            Err(err) => Err(err.to_string()),
+        },
+        Command::CheckConfig => Config::load(config_path)
+            .map(|config| cli::check_config(&config))
+            .map_err(|err| err.to_string()),
+        Command::Query { sink, query } => match Config::load(config_path) {
+            Ok(config) => cli::query(&config, sink.as_deref(), query),
+            Err(err) => Err(err.to_string()),
+        },
+        Command::Snapshot { guid, json } => match battlelog_client(config_path) {
+            Ok(client) => cli::snapshot(&client, &guid, json).await.map_err(|err| err.to_string()),
+            Err(err) => Err(err),
+        },
+        Command::Info { guid } => match battlelog_client(config_path) {
+            Ok(client) => cli::info(&client, &guid).await.map_err(|err| err.to_string()),
+            Err(err) => Err(err),
+        },
+        Command::Browse { name, region, mode, map, min_players, max_players, limit } => {
+            let discovery = DiscoveryConfig {
+                name,
+                regions: region,
+                game_modes: mode,
+                maps: map,
+                min_players,
+                max_players,
+                limit,
+                ..DiscoveryConfig::default()
+            };
+            match (battlelog_client(config_path), discovery.query()) {
+                (Ok(client), Ok(query)) => cli::browse(&client, &query).await.map_err(|err| err.to_string()),
+                (Err(err), _) | (_, Err(err)) => Err(err),
             }
         }
-        Err(_) => {}
+        Command::Search { name, json } => match battlelog_client(config_path) {
+            Ok(client) => cli::search(&client, &name, json).await.map_err(|err| err.to_string()),
+            Err(err) => Err(err),
+        },
+        Command::Player { persona_id, json } => match battlelog_client(config_path) {
+            Ok(client) => cli::player(&client, persona_id, json).await.map_err(|err| err.to_string()),
+            Err(err) => Err(err),
+        },
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(1);
     }
 }
 
-#[tokio::main]
-async fn main() {
-    dotenv().ok();
+fn battlelog_client(config_path: Option<&Path>) -> Result<BattlelogClient, String> {
+    let config = BattlelogConfig::load(config_path).map_err(|err| err.to_string())?;
+    config.client().map_err(|err| err.to_string())
+}
 
-    let database_url = dotenv::var("DATABASE_URL").unwrap_or("http://localhost:8086".to_string());
-    let database_name = dotenv::var("DATABASE_NAME").unwrap_or("bflogger".to_string());
-    let interval: u64 = dotenv::var("INTERVAL")
-        .map(|var| var.parse::<u64>())
-        .unwrap_or(Ok(30000))
-        .unwrap();
-    
-    let server_guids = dotenv::var("SERVER_GUID")
-        .expect("Server guid(s) needed. Separate with comma (,) if multiple.");
-
-    let mut jhs = Vec::new();
-    let split = server_guids.split(",");
-
-    for s in split {
-        let url = database_url.clone();
-        let name = database_name.clone();
-        let guid = String::from(s);
-        jhs.push(tokio::spawn(async move {
-            // Connect to database
-            let client = Client::new(url, name);
-
-            println!("Starting fetch loop for server guid {} with the interval of {}", &guid, interval);
-
-            loop {
-                log_new_entry(&client, &guid).await;
-                sleep(Duration::from_millis(interval)).await;
-            }
-        }));
+/// Polls the servers until SIGTERM or Ctrl+C, or until every server has been stopped, then writes
+/// what the sinks still have queued.
+///
+/// The servers follow the changes of the config file, the admin API and the discovery while running.
+async fn run(config: Config, config_path: Option<&Path>) -> Result<(), String> {
+    // One pooled client shared by every server task. The base URLs can be pointed
+    // at a local stand-in for testing without the live EA servers.
+    let battlelog = config.battlelog.client().map_err(|err| format!("Failed to create the Battlelog client: {}", err))?;
+
+    let metrics = Arc::new(Metrics::default());
+    if let Some(addr) = config.metrics.listen {
+        tokio::spawn(metrics::serve(addr, metrics.clone()));
+    }
+
+    // Shared by every server that writes to them, so the files are only opened once
+    let queues: BTreeMap<_, _> = sinks::build(&config.sinks)
+        .and_then(|sinks| {
+            sinks
+                .into_iter()
+                .map(|(name, sink)| Ok((name.to_string(), SinkQueue::start(&name, sink, &config.queue, metrics.clone())?)))
+                .collect()
+        })
+        .map_err(|err| format!("Failed to create the sinks: {}", err))?;
+
+    let context = Context {
+        battlelog: battlelog.clone(),
+        metrics,
+        queues: queues.clone(),
+        rate_limit: Arc::new(RateLimit::default()),
+        polling: config.polling.clone(),
+    };
+    let admin = config.admin.clone();
+    let discovery = config.discovery.clone();
+    let pool = ServerPool::start(context, config);
+
+    // The discovery starts servers by itself, without it the logger is done once the last one stops
+    let discovering = discovery.is_some();
+    if let Some(discovery) = discovery {
+        tokio::spawn(servers::discover(pool.clone(), battlelog, discovery));
+    }
 
-        sleep(Duration::from_millis(2000)).await;
+    let config_file = config::config_file(config_path);
+    if let Some(path) = &config_file {
+        tokio::spawn(servers::watch_config(pool.clone(), path.clone()));
     }
+    if let Some(addr) = admin.listen {
+        let pool = pool.clone();
+        tokio::spawn(async move { admin::serve(addr, &admin, pool, config_file).await });
+    }
+
+    tokio::select! {
+        _ = supervisor::signal() => eprintln!("Shutting down, writing the queued points"),
+        _ = pool.finished(), if !discovering => eprintln!("All servers stopped, writing the queued points"),
+    }
+    pool.stop_all().await;
 
-    // Wait for all our spawned tasks to finish.
-    for jh in jhs.drain(..) {
-        jh.await.unwrap()
+    let deadline = tokio::time::Instant::now() + FLUSH_TIMEOUT;
+    let mut dropped = 0;
+    for queue in queues.values() {
+        dropped += queue.flush(deadline).await;
     }
+    if dropped > 0 {
+        return Err(format!("{} points couldn't be written before exiting", dropped));
+    }
+    eprintln!("Stopped");
+    Ok(())
+}
+
+/// A recorded Keeper response from `battlelog/fixtures`, for example `snapshot_rush`.
+#[cfg(test)]
+pub(crate) fn fixture(name: &str) -> battlelog::KeeperResponse {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../battlelog/fixtures")
+        .join(format!("{}.json", name));
+    let json = std::fs::read_to_string(&path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+    serde_json::from_str(&json).unwrap_or_else(|err| panic!("{}: {}", path.display(), err))
 }
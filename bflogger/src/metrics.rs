@@ -0,0 +1,333 @@
+//! Prometheus exporter with the latest state of every server.
+//!
+//! The values are kept in memory and rendered in the text exposition format on every scrape of
+//! `/metrics`, there's nothing to configure per server.
+
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use battlelog::Snapshot;
+use chrono::{DateTime, Utc};
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+
+use crate::config::ServerConfig;
+use crate::poll::PollStatus;
+use crate::round::team_scores;
+
+const CONTENT_TYPE_TEXT: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Name, help text and value of a gauge with one series per server.
+type Gauge = (&'static str, &'static str, fn(&ServerMetrics) -> f64);
+
+const GAUGES: &[Gauge] = &[
+    ("bflogger_players", "Players on the server", |s| f64::from(s.players)),
+    ("bflogger_waiting_players", "Players in the join queue", |s| f64::from(s.waiting_players)),
+    ("bflogger_max_players", "Player slots of the server", |s| f64::from(s.max_players)),
+    ("bflogger_round_time_seconds", "Time played in the current round", |s| f64::from(s.round_time)),
+    (
+        "bflogger_last_successful_poll_timestamp_seconds",
+        "Unix time of the last successful poll",
+        |s| s.last_success.timestamp_millis() as f64 / 1000.0,
+    ),
+];
+
+/// State of a server as of its last successful poll.
+#[derive(Debug, Clone)]
+struct ServerMetrics {
+    label: String,
+    current_map: String,
+    game_mode: String,
+    players: u16,
+    waiting_players: u8,
+    max_players: u8,
+    round_time: u32,
+    /// Tickets of every team, or the primary score of the modes without tickets
+    tickets: BTreeMap<u8, u32>,
+    last_success: DateTime<Utc>,
+}
+
+/// Outcome of the polls of a server, kept apart since a server may never have succeeded.
+#[derive(Debug, Clone, Default)]
+struct PollMetrics {
+    label: String,
+    consecutive_failures: u32,
+    /// Polls so far by status
+    polls: BTreeMap<&'static str, u64>,
+}
+
+/// Write queue of a sink.
+#[derive(Debug, Clone, Default)]
+struct SinkMetrics {
+    memory_points: usize,
+    spooled_points: usize,
+    dropped_points: u64,
+}
+
+/// Latest values of every server and sink, shared by the poll loops and the HTTP server.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    servers: Mutex<BTreeMap<String, ServerMetrics>>,
+    polls: Mutex<BTreeMap<String, PollMetrics>>,
+    sinks: Mutex<BTreeMap<String, SinkMetrics>>,
+}
+
+impl Metrics {
+    /// Records a successful poll of the server.
+    pub fn update(&self, server: &ServerConfig, time: DateTime<Utc>, snapshot: &Snapshot) {
+        let metrics = ServerMetrics {
+            label: server.label.as_deref().unwrap_or("").to_string(),
+            current_map: snapshot.get_map().id().to_string(),
+            game_mode: snapshot.game_mode.to_string(),
+            players: snapshot.get_players_count(),
+            waiting_players: snapshot.waiting_players,
+            max_players: snapshot.max_players,
+            round_time: snapshot.round_time,
+            tickets: team_scores(snapshot),
+            last_success: time,
+        };
+
+        self.servers
+            .lock()
+            .expect("Metrics lock poisoned")
+            .insert(server.guid.to_string(), metrics);
+    }
+
+    /// Counts a poll of the server, successful or not.
+    pub fn record_poll(&self, server: &ServerConfig, status: PollStatus, consecutive_failures: u32) {
+        let mut polls = self.polls.lock().expect("Metrics lock poisoned");
+        let metrics = polls.entry(server.guid.to_string()).or_default();
+        metrics.label = server.label.as_deref().unwrap_or("").to_string();
+        metrics.consecutive_failures = consecutive_failures;
+        *metrics.polls.entry(status.as_str()).or_default() += 1;
+    }
+
+    /// Forgets the values of a server that is no longer polled.
+    pub fn remove(&self, guid: &str) {
+        let guid = guid.to_lowercase();
+        self.servers
+            .lock()
+            .expect("Metrics lock poisoned")
+            .retain(|key, _| key.to_lowercase() != guid);
+        self.polls
+            .lock()
+            .expect("Metrics lock poisoned")
+            .retain(|key, _| key.to_lowercase() != guid);
+    }
+
+    /// Records the records waiting to be written to the sink.
+    pub fn set_queue(&self, sink: &str, memory_points: usize, spooled_points: usize) {
+        let mut sinks = self.sinks.lock().expect("Metrics lock poisoned");
+        let metrics = sinks.entry(sink.to_string()).or_default();
+        metrics.memory_points = memory_points;
+        metrics.spooled_points = spooled_points;
+    }
+
+    /// Counts the records that the queue of the sink had no room for.
+    pub fn add_dropped(&self, sink: &str, points: usize) {
+        let mut sinks = self.sinks.lock().expect("Metrics lock poisoned");
+        sinks.entry(sink.to_string()).or_default().dropped_points += points as u64;
+    }
+
+    /// Renders every metric in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let servers = self.servers.lock().expect("Metrics lock poisoned");
+        let mut out = String::new();
+
+        for (name, help, value) in GAUGES {
+            header(&mut out, name, "gauge", help);
+            for (guid, server) in servers.iter() {
+                let _ = writeln!(out, "{}{{{}}} {}", name, labels(guid, server), value(server));
+            }
+        }
+
+        header(
+            &mut out,
+            "bflogger_team_tickets",
+            "gauge",
+            "Tickets of the team, or the primary score of the game modes without tickets",
+        );
+        for (guid, server) in servers.iter() {
+            for (team, tickets) in &server.tickets {
+                let _ = writeln!(
+                    out,
+                    "bflogger_team_tickets{{{},team=\"{}\"}} {}",
+                    labels(guid, server),
+                    team,
+                    tickets
+                );
+            }
+        }
+        drop(servers);
+
+        let polls = self.polls.lock().expect("Metrics lock poisoned");
+        header(
+            &mut out,
+            "bflogger_consecutive_poll_failures",
+            "gauge",
+            "Failed polls of the server in a row, zero after a success",
+        );
+        for (guid, metrics) in polls.iter() {
+            let _ = writeln!(
+                out,
+                "bflogger_consecutive_poll_failures{{guid=\"{}\",label=\"{}\"}} {}",
+                escape(guid),
+                escape(&metrics.label),
+                metrics.consecutive_failures
+            );
+        }
+        header(&mut out, "bflogger_polls_total", "counter", "Polls of the server by outcome");
+        for (guid, metrics) in polls.iter() {
+            for (status, count) in &metrics.polls {
+                let _ = writeln!(
+                    out,
+                    "bflogger_polls_total{{guid=\"{}\",label=\"{}\",status=\"{}\"}} {}",
+                    escape(guid),
+                    escape(&metrics.label),
+                    status,
+                    count
+                );
+            }
+        }
+        drop(polls);
+
+        let sinks = self.sinks.lock().expect("Metrics lock poisoned");
+        header(
+            &mut out,
+            "bflogger_sink_queued_points",
+            "gauge",
+            "Records waiting to be written to the sink, in memory or in the spool file",
+        );
+        for (sink, metrics) in sinks.iter() {
+            for (location, points) in &[("memory", metrics.memory_points), ("spool", metrics.spooled_points)] {
+                let _ = writeln!(
+                    out,
+                    "bflogger_sink_queued_points{{sink=\"{}\",location=\"{}\"}} {}",
+                    escape(sink),
+                    location,
+                    points
+                );
+            }
+        }
+        header(
+            &mut out,
+            "bflogger_sink_dropped_points_total",
+            "counter",
+            "Records dropped because the queue of the sink was full or the sink rejected them",
+        );
+        for (sink, metrics) in sinks.iter() {
+            let _ = writeln!(
+                out,
+                "bflogger_sink_dropped_points_total{{sink=\"{}\"}} {}",
+                escape(sink),
+                metrics.dropped_points
+            );
+        }
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn labels(guid: &str, server: &ServerMetrics) -> String {
+    format!(
+        "guid=\"{}\",label=\"{}\",map=\"{}\",mode=\"{}\"",
+        escape(guid),
+        escape(&server.label),
+        escape(&server.current_map),
+        escape(&server.game_mode)
+    )
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Serves `/metrics` until the process exits.
+pub async fn serve(addr: SocketAddr, metrics: Arc<Metrics>) {
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let metrics = metrics.clone();
+                async move { Ok::<_, Infallible>(handle(&request, &metrics)) }
+            }))
+        }
+    });
+
+    let server = match Server::try_bind(&addr) {
+        Ok(builder) => builder.serve(make_service),
+        Err(err) => return eprintln!("Failed to listen for metrics on {}: {}", addr, err),
+    };
+    eprintln!("Serving Prometheus metrics on http://{}/metrics", addr);
+    if let Err(err) = server.await {
+        eprintln!("Metrics server failed: {}", err);
+    }
+}
+
+fn handle(request: &Request<Body>, metrics: &Metrics) -> Response<Body> {
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header(CONTENT_TYPE, CONTENT_TYPE_TEXT)
+            .body(Body::from(metrics.render()))
+            .expect("valid response"),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("Not found, try /metrics\n"))
+            .expect("valid response"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let data = crate::fixture("snapshot_conquest");
+        let server = ServerConfig {
+            guid: "4d0151b3-81ff-4268-b4e8-5e60d5bc8765".to_string(),
+            label: Some("BattleFox \"#1\"".to_string()),
+            interval: 30000,
+            tags: BTreeMap::new(),
+            measurements: Default::default(),
+            sinks: Vec::new(),
+        };
+        let metrics = Metrics::default();
+        metrics.update(&server, Utc::now(), &data.snapshot);
+        metrics.record_poll(&server, PollStatus::Timeout, 1);
+        metrics.record_poll(&server, PollStatus::Timeout, 2);
+        metrics.set_queue("influxdb", 120, 0);
+        metrics.add_dropped("influxdb", 7);
+
+        let text = metrics.render();
+        let labels = "guid=\"4d0151b3-81ff-4268-b4e8-5e60d5bc8765\",label=\"BattleFox \\\"#1\\\"\",map=\"MP_Prison\",mode=\"ConquestLarge0\"";
+        assert!(text.contains(&format!("bflogger_players{{{}}} 2\n", labels)), "{}", text);
+        assert!(text.contains(&format!("bflogger_team_tickets{{{},team=\"1\"}} 412\n", labels)), "{}", text);
+        assert!(text.contains("# TYPE bflogger_round_time_seconds gauge\n"), "{}", text);
+        let poll_labels = "guid=\"4d0151b3-81ff-4268-b4e8-5e60d5bc8765\",label=\"BattleFox \\\"#1\\\"\"";
+        assert!(text.contains(&format!("bflogger_consecutive_poll_failures{{{}}} 2\n", poll_labels)), "{}", text);
+        assert!(text.contains(&format!("bflogger_polls_total{{{},status=\"timeout\"}} 2\n", poll_labels)), "{}", text);
+        assert!(text.contains("bflogger_sink_queued_points{sink=\"influxdb\",location=\"memory\"} 120\n"), "{}", text);
+        assert!(text.contains("# TYPE bflogger_sink_dropped_points_total counter\n"), "{}", text);
+        assert!(text.contains("bflogger_sink_dropped_points_total{sink=\"influxdb\"} 7\n"), "{}", text);
+
+        // A stopped server leaves no stale values behind, the sinks stay
+        metrics.remove("4D0151B3-81FF-4268-B4E8-5E60D5BC8765");
+        let text = metrics.render();
+        assert!(!text.contains("4d0151b3-81ff-4268-b4e8-5e60d5bc8765"), "{}", text);
+        assert!(text.contains("bflogger_sink_dropped_points_total{sink=\"influxdb\"} 7\n"), "{}", text);
+    }
+}
@@ -0,0 +1,292 @@
+//! Outcome of every snapshot fetch, so the servers that stop reporting can be told apart from
+//! the ones that are just empty, and the scheduling of the next one.
+//!
+//! A server is polled less often while it's empty or failing and more often near the end of a
+//! round, where the final scores are decided. A 429 from keeper pauses every server, since the
+//! rate limit applies to the whole logger and not to a single server.
+
+use std::{
+    error::Error as StdError,
+    fmt,
+    sync::Mutex,
+    time::Duration,
+};
+
+use rand::Rng;
+use tokio::time::{sleep_until, Instant};
+
+use crate::config::{PollingConfig, MIN_INTERVAL};
+
+/// How a poll of a server went, the `status` tag of the `poll_status` measurement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PollStatus {
+    Success,
+    /// Keeper answered with an error, or couldn't be reached at all
+    HttpError,
+    /// The snapshot didn't match the expected schema
+    DecodeError,
+    /// No answer within the request timeout
+    Timeout,
+}
+
+impl PollStatus {
+    pub fn from_error(err: &battlelog::Error) -> Self {
+        match err {
+            err if err.is_timeout() => PollStatus::Timeout,
+            battlelog::Error::Deserialize { .. } => PollStatus::DecodeError,
+            _ => PollStatus::HttpError,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PollStatus::Success => "success",
+            PollStatus::HttpError => "http_error",
+            PollStatus::DecodeError => "decode_error",
+            PollStatus::Timeout => "timeout",
+        }
+    }
+}
+
+impl fmt::Display for PollStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What the scheduling of the next poll depends on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PollOutcome {
+    Success {
+        players: u16,
+        /// See [`round_progress`](crate::round::round_progress)
+        round_progress: Option<f64>,
+    },
+    Failed {
+        /// Failed polls in a row, including this one
+        failures: u32,
+        /// Keeper answered with a 429
+        rate_limited: bool,
+        retry_after: Option<Duration>,
+    },
+}
+
+impl PollOutcome {
+    pub fn failed(err: &battlelog::Error, failures: u32) -> Self {
+        let retry_after = match err {
+            battlelog::Error::RateLimited { retry_after } => *retry_after,
+            _ => None,
+        };
+        PollOutcome::Failed {
+            failures,
+            rate_limited: matches!(err, battlelog::Error::RateLimited { .. }),
+            retry_after,
+        }
+    }
+}
+
+/// Picks the delays between the polls of a server.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    interval: Duration,
+    config: PollingConfig,
+}
+
+impl Scheduler {
+    pub fn new(interval: u64, config: &PollingConfig) -> Self {
+        Self {
+            interval: Duration::from_millis(interval),
+            config: config.clone(),
+        }
+    }
+
+    /// A random point within the first interval, so the servers started together are spread out.
+    pub fn initial_delay(&self, rng: &mut impl Rng) -> Duration {
+        self.interval.mul_f64(rng.gen_range(0.0..1.0))
+    }
+
+    pub fn next_delay(&self, outcome: &PollOutcome, rng: &mut impl Rng) -> Duration {
+        let delay = match outcome {
+            PollOutcome::Success { players: 0, .. } => self.interval.mul_f64(self.config.empty_factor),
+            PollOutcome::Success { round_progress: Some(progress), .. } if *progress >= self.config.round_end_progress => {
+                self.interval.mul_f64(self.config.round_end_factor)
+            }
+            PollOutcome::Success { .. } => self.interval,
+            PollOutcome::Failed { failures, .. } => {
+                // A single failure doesn't slow down, the second one doubles the interval and so on
+                let doublings = failures.saturating_sub(1).min(16);
+                self.interval
+                    .saturating_mul(1 << doublings)
+                    .min(Duration::from_millis(self.config.max_backoff))
+                    .max(self.interval)
+            }
+        };
+
+        let jitter = if self.config.jitter > 0.0 {
+            rng.gen_range(-self.config.jitter..self.config.jitter)
+        } else {
+            0.0
+        };
+        let delay = delay.mul_f64(1.0 + jitter).max(Duration::from_millis(MIN_INTERVAL));
+
+        match outcome {
+            PollOutcome::Failed { retry_after: Some(retry_after), .. } => delay.max(*retry_after),
+            _ => delay,
+        }
+    }
+}
+
+/// Shared by the tasks of every server, holds all of them back after keeper asked to slow down.
+#[derive(Debug, Default)]
+pub struct RateLimit {
+    until: Mutex<Option<Instant>>,
+}
+
+impl RateLimit {
+    /// Keeps every server from polling for the given time, unless they're already held back longer.
+    pub fn hold_off(&self, duration: Duration) {
+        let until = Instant::now() + duration;
+        let mut current = self.until.lock().expect("Rate limit lock poisoned");
+        if current.is_none_or(|current| current < until) {
+            eprintln!("Keeper is rate limiting, pausing every server for {} s", duration.as_secs());
+            *current = Some(until);
+        }
+    }
+
+    /// Waits until the servers are allowed to poll again.
+    pub async fn wait(&self) {
+        let until = *self.until.lock().expect("Rate limit lock poisoned");
+        if let Some(until) = until {
+            sleep_until(until).await;
+        }
+    }
+}
+
+/// The error followed by every cause that adds something to its message, separated by colons.
+pub fn error_chain(err: &dyn StdError) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(cause) = source {
+        let cause_message = cause.to_string();
+        // The wrapping errors often repeat the message of their source
+        if !message.contains(&cause_message) {
+            message.push_str(": ");
+            message.push_str(&cause_message);
+        }
+        source = cause.source();
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use battlelog::BattlelogClient;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    const GUID: &str = "4d0151b3-81ff-4268-b4e8-5e60d5bc8765";
+
+    async fn poll(response: ResponseTemplate) -> battlelog::Error {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(format!("/snapshot/{}", GUID)))
+            .respond_with(response)
+            .mount(&server)
+            .await;
+        let client = BattlelogClient::builder()
+            .keeper_url(server.uri())
+            .timeout(Duration::from_millis(200))
+            .build()
+            .unwrap();
+        client.server_snapshot(GUID).await.unwrap_err()
+    }
+
+    fn scheduler(jitter: f64) -> Scheduler {
+        Scheduler::new(30000, &PollingConfig { jitter, ..PollingConfig::default() })
+    }
+
+    fn success(players: u16, round_progress: Option<f64>) -> PollOutcome {
+        PollOutcome::Success { players, round_progress }
+    }
+
+    fn failure(failures: u32, retry_after: Option<u64>) -> PollOutcome {
+        PollOutcome::Failed {
+            failures,
+            rate_limited: retry_after.is_some(),
+            retry_after: retry_after.map(Duration::from_secs),
+        }
+    }
+
+    #[test]
+    fn next_delay() {
+        let scheduler = scheduler(0.0);
+        let rng = &mut rand::thread_rng();
+        let mut secs = |outcome| scheduler.next_delay(&outcome, rng).as_secs();
+
+        assert_eq!(30, secs(success(40, Some(0.5))));
+        assert_eq!(30, secs(success(40, None)));
+        assert_eq!(120, secs(success(0, Some(0.95))));
+        assert_eq!(15, secs(success(40, Some(0.95))));
+
+        assert_eq!(30, secs(failure(1, None)));
+        assert_eq!(60, secs(failure(2, None)));
+        assert_eq!(240, secs(failure(4, None)));
+        assert_eq!(300, secs(failure(50, None)));
+        assert_eq!(600, secs(failure(1, Some(600))));
+    }
+
+    #[test]
+    fn jitter() {
+        let scheduler = scheduler(0.1);
+        let rng = &mut rand::thread_rng();
+        let delays: Vec<Duration> = (0..100).map(|_| scheduler.next_delay(&success(40, None), rng)).collect();
+
+        assert!(delays.iter().all(|delay| *delay >= Duration::from_secs(27) && *delay <= Duration::from_secs(33)));
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+        assert!(scheduler.initial_delay(rng) < Duration::from_secs(30));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit() {
+        let limit = RateLimit::default();
+        let start = Instant::now();
+        limit.wait().await;
+        assert_eq!(start, Instant::now());
+
+        limit.hold_off(Duration::from_secs(60));
+        limit.hold_off(Duration::from_secs(10));
+        limit.wait().await;
+        assert_eq!(Duration::from_secs(60), start.elapsed());
+    }
+
+    #[tokio::test]
+    async fn classify() {
+        let err = poll(ResponseTemplate::new(503).set_body_string("maintenance")).await;
+        assert_eq!(PollStatus::HttpError, PollStatus::from_error(&err));
+
+        let err = poll(ResponseTemplate::new(200).set_body_string(r#"{"snapshot": {}}"#)).await;
+        assert_eq!(PollStatus::DecodeError, PollStatus::from_error(&err));
+        assert!(error_chain(&err).contains("snapshot"), "{}", error_chain(&err));
+
+        let err = poll(ResponseTemplate::new(200).set_delay(Duration::from_secs(2))).await;
+        assert_eq!(PollStatus::Timeout, PollStatus::from_error(&err));
+    }
+
+    #[tokio::test]
+    async fn error_chain_includes_causes() {
+        let client = BattlelogClient::builder().keeper_url("http://127.0.0.1:9").build().unwrap();
+        let err = client.server_snapshot(GUID).await.unwrap_err();
+
+        assert_eq!(PollStatus::HttpError, PollStatus::from_error(&err));
+        let message = error_chain(&err);
+        assert!(message.starts_with("request failed: "), "{}", message);
+        assert!(message.contains("Connection refused"), "{}", message);
+        // Nothing is repeated even though every level of the chain wraps the next one
+        assert_eq!(1, message.matches("error sending request").count(), "{}", message);
+    }
+}
@@ -0,0 +1,714 @@
+//! The normalized records that a poll of a server produces, independent of where they're written.
+//!
+//! Every reading is both [`InfluxDbWriteable`] and [`Serialize`], so the sinks can turn them into
+//! points, JSON lines or CSV rows without knowing the fields. The batches also deserialize, for
+//! the spool files of the sink queues.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use battlelog::{GameMode, GameModeState, Map, RotationEntry, Snapshot};
+use chrono::{DateTime, Utc};
+use influxdb::{InfluxDbWriteable, WriteQuery};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map as JsonMap, Value};
+
+use crate::config::ServerConfig;
+use crate::poll::PollStatus;
+use crate::round::{RoundEvent, RoundSummary};
+use crate::session::SessionEvent;
+
+/// A single point of one of the measurements.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "measurement", rename_all = "snake_case")]
+pub enum Record {
+    Snapshot(SnapshotReading),
+    Team(TeamReading),
+    Player(PlayerReading),
+    RoundEvent(RoundEventReading),
+    RoundSummary(RoundSummaryReading),
+    SessionEvent(SessionEventReading),
+    PollStatus(PollStatusReading),
+    Rotation(RotationReading),
+}
+
+impl Record {
+    /// Name of the measurement, the same for every sink.
+    pub fn measurement(&self) -> &'static str {
+        match self {
+            Record::Snapshot(_) => "snapshot",
+            Record::Team(_) => "team",
+            Record::Player(_) => "player",
+            Record::RoundEvent(_) => "round_event",
+            Record::RoundSummary(_) => "round_summary",
+            Record::SessionEvent(_) => "session_event",
+            Record::PollStatus(_) => "poll_status",
+            Record::Rotation(_) => "rotation",
+        }
+    }
+
+    pub fn into_query(self) -> WriteQuery {
+        let measurement = self.measurement();
+        match self {
+            Record::Snapshot(reading) => reading.into_query(measurement),
+            Record::Team(reading) => reading.into_query(measurement),
+            Record::Player(reading) => reading.into_query(measurement),
+            Record::RoundEvent(reading) => reading.into_query(measurement),
+            Record::RoundSummary(reading) => reading.into_query(measurement),
+            Record::SessionEvent(reading) => reading.into_query(measurement),
+            Record::PollStatus(reading) => reading.into_query(measurement),
+            Record::Rotation(reading) => reading.into_query(measurement),
+        }
+    }
+
+    /// The fields and tags of the record as a JSON object, with the measurement name first.
+    pub fn to_json(&self) -> Result<JsonMap<String, Value>, serde_json::Error> {
+        match serde_json::to_value(self)? {
+            Value::Object(map) => Ok(map),
+            _ => unreachable!("records always serialize to an object"),
+        }
+    }
+}
+
+/// The records of a single poll of a server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordBatch {
+    /// The `server_label` and the extra tags of the server, which the sinks add to every record.
+    pub tags: BTreeMap<String, String>,
+    /// Name of the server on Battlelog once it's fetched, which the sinks add to every record as
+    /// a field. Not a tag, a rename or the first fetch would start new series.
+    #[serde(default)]
+    pub server_name: Option<String>,
+    pub records: Vec<Record>,
+}
+
+impl RecordBatch {
+    pub fn new(server: &ServerConfig, server_name: Option<&str>) -> Self {
+        let mut tags = server.tags.clone();
+        if let Some(label) = &server.label {
+            tags.insert("server_label".to_string(), label.to_string());
+        }
+
+        Self {
+            tags,
+            server_name: server_name.map(str::to_string),
+            records: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Every record as a JSON object that also includes the server name and tags.
+    pub fn to_json(&self) -> Result<Vec<JsonMap<String, Value>>, serde_json::Error> {
+        self.records
+            .iter()
+            .map(|record| {
+                let mut object = record.to_json()?;
+                object.insert("server_name".to_string(), self.server_name.clone().into());
+                for (key, value) in &self.tags {
+                    // The fields of the record win over an extra tag of the same name
+                    object.entry(key.as_str()).or_insert_with(|| Value::String(value.to_string()));
+                }
+                Ok(object)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, InfluxDbWriteable)]
+pub struct SnapshotReading {
+    pub time: DateTime<Utc>,
+    #[influxdb(tag)]
+    pub server_guid: String,
+    pub game_id: u64,
+    #[influxdb(tag)]
+    pub game_mode: String,
+    pub game_mode_name: String,
+    pub map_variant: u8,
+    #[influxdb(tag)]
+    pub current_map: String,
+    pub current_map_name: String,
+    pub max_players: u8,
+    pub waiting_players: u8,
+    pub players: u16,
+    pub round_time: u32,
+    pub default_round_time_multiplier: u32,
+
+    #[influxdb(tag)]
+    pub round_running: bool,
+
+    pub round_running_val: bool,
+
+    pub defender_team: Option<u8>,
+    pub defender_bases: Option<u8>,
+    pub defender_bases_max: Option<u8>,
+    pub defender_attacker: Option<u8>,
+
+    pub attacker_team: Option<u8>,
+    pub attacker_tickets: Option<u16>,
+    pub attacker_tickets_max: Option<u16>,
+    pub attacker_attacker: Option<u8>,
+}
+
+impl SnapshotReading {
+    pub fn new(time: DateTime<Utc>, server_guid: &str, snapshot: &Snapshot) -> Self {
+        // The game mode block is missing between rounds, no matter the mode
+        let round_running = snapshot.game_mode_state().is_some();
+
+        let mut reading = Self {
+            time,
+            server_guid: server_guid.to_string(),
+            game_id: snapshot.game_id,
+            game_mode: snapshot.game_mode.to_string(),
+            game_mode_name: snapshot.get_game_mode().name().to_string(),
+            map_variant: snapshot.map_variant,
+            current_map: snapshot.get_map().id().to_string(),
+            current_map_name: snapshot.get_map().name().to_string(),
+            max_players: snapshot.max_players,
+            waiting_players: snapshot.waiting_players,
+            players: snapshot.get_players_count(),
+            round_time: snapshot.round_time,
+            default_round_time_multiplier: snapshot.default_round_time_multiplier,
+
+            round_running,
+
+            round_running_val: round_running,
+
+            defender_team: None,
+            defender_bases: None,
+            defender_bases_max: None,
+            defender_attacker: None,
+
+            attacker_team: None,
+            attacker_tickets: None,
+            attacker_tickets_max: None,
+            attacker_attacker: None,
+        };
+
+        if let Some(rush) = &snapshot.rush {
+            let defenders = &rush.defenders;
+            reading.defender_team = Some(defenders.team);
+            reading.defender_bases = Some(defenders.bases);
+            reading.defender_bases_max = Some(defenders.bases_max);
+            reading.defender_attacker = Some(defenders.attacker);
+
+            let attackers = &rush.attackers;
+            reading.attacker_team = Some(attackers.team);
+            reading.attacker_tickets = Some(attackers.tickets);
+            reading.attacker_tickets_max = Some(attackers.tickets_max);
+            reading.attacker_attacker = Some(attackers.attacker);
+        }
+
+        reading
+    }
+}
+
+/// Per-team scoreboard state, written into the `team` measurement.
+///
+/// Only the fields of the game mode being played are set.
+#[derive(Debug, Clone, Serialize, Deserialize, InfluxDbWriteable)]
+pub struct TeamReading {
+    pub time: DateTime<Utc>,
+    #[influxdb(tag)]
+    pub server_guid: String,
+    #[influxdb(tag)]
+    pub game_mode: String,
+    #[influxdb(tag)]
+    pub team: u8,
+
+    // Conquest, Domination, Chain Link, Air Superiority and Rush attackers
+    pub tickets: Option<u32>,
+    pub tickets_max: Option<u32>,
+
+    // Rush defenders
+    pub bases: Option<u8>,
+    pub bases_max: Option<u8>,
+
+    // Team Deathmatch and Squad Deathmatch
+    pub kills: Option<u32>,
+    pub kills_max: Option<u32>,
+
+    // Carrier Assault
+    pub destroyed_crates: Option<u8>,
+    pub carrier_health: Option<u8>,
+
+    // Obliteration and Squad Obliteration
+    pub score: Option<u32>,
+    pub score_max: Option<u32>,
+
+    // Capture the Flag
+    pub flags: Option<u8>,
+    pub flags_max: Option<u8>,
+
+    // Defuse
+    pub rounds: Option<u8>,
+    pub rounds_max: Option<u8>,
+}
+
+impl TeamReading {
+    pub fn new(time: DateTime<Utc>, server_guid: &str, game_mode: &str, team: u8) -> Self {
+        Self {
+            time,
+            server_guid: server_guid.to_string(),
+            game_mode: game_mode.to_string(),
+            team,
+            tickets: None,
+            tickets_max: None,
+            bases: None,
+            bases_max: None,
+            kills: None,
+            kills_max: None,
+            destroyed_crates: None,
+            carrier_health: None,
+            score: None,
+            score_max: None,
+            flags: None,
+            flags_max: None,
+            rounds: None,
+            rounds_max: None,
+        }
+    }
+}
+
+/// Scoreboard state of a single player, written into the `player` measurement.
+#[derive(Debug, Clone, Serialize, Deserialize, InfluxDbWriteable)]
+pub struct PlayerReading {
+    pub time: DateTime<Utc>,
+    #[influxdb(tag)]
+    pub server_guid: String,
+    #[influxdb(tag)]
+    pub persona_id: u64,
+    #[influxdb(tag)]
+    pub name: String,
+    /// `None` without a clan, InfluxDB doesn't take empty tags
+    #[influxdb(tag)]
+    pub clan_tag: Option<String>,
+    #[influxdb(tag)]
+    pub team: u8,
+    pub game_id: u64,
+    pub score: u32,
+    pub kills: u32,
+    pub deaths: u32,
+    pub kd: f64,
+    pub squad: i8,
+    pub rank: i16,
+    pub role: u8,
+}
+
+pub fn player_readings(time: DateTime<Utc>, server_guid: &str, snapshot: &Snapshot) -> Vec<PlayerReading> {
+    snapshot.team_info
+        .iter()
+        .flat_map(|(team, team_info)| {
+            team_info.players.iter().map(move |(persona_id, player)| PlayerReading {
+                time,
+                server_guid: server_guid.to_string(),
+                persona_id: *persona_id,
+                name: player.name.to_string(),
+                clan_tag: Some(player.tag.to_string()).filter(|tag| !tag.is_empty()),
+                team: *team,
+                game_id: snapshot.game_id,
+                score: player.score,
+                kills: player.kills,
+                deaths: player.deaths,
+                // Same as the in-game scoreboard, zero deaths counts as one
+                kd: f64::from(player.kills) / f64::from(player.deaths.max(1)),
+                squad: player.squad,
+                rank: player.rank,
+                role: player.role,
+            })
+        })
+        .collect()
+}
+
+pub fn team_readings(time: DateTime<Utc>, server_guid: &str, snapshot: &Snapshot) -> Vec<TeamReading> {
+    let mut readings = Vec::new();
+    let mut push = |team: u8, set: &dyn Fn(&mut TeamReading)| {
+        let mut reading = TeamReading::new(time, server_guid, &snapshot.game_mode, team);
+        set(&mut reading);
+        readings.push(reading);
+    };
+
+    match snapshot.game_mode_state() {
+        Some(GameModeState::Rush(rush)) => {
+            push(rush.attackers.team, &|r| {
+                r.tickets = Some(rush.attackers.tickets.into());
+                r.tickets_max = Some(rush.attackers.tickets_max.into());
+            });
+            push(rush.defenders.team, &|r| {
+                r.bases = Some(rush.defenders.bases);
+                r.bases_max = Some(rush.defenders.bases_max);
+            });
+        }
+        Some(GameModeState::Conquest(teams)) => {
+            for (team, state) in teams {
+                push(*team, &|r| {
+                    r.tickets = Some(state.tickets);
+                    r.tickets_max = Some(state.tickets_max);
+                });
+            }
+        }
+        Some(GameModeState::Domination(teams)) => {
+            for (team, state) in teams {
+                push(*team, &|r| {
+                    r.tickets = Some(state.tickets);
+                    r.tickets_max = Some(state.tickets_max);
+                });
+            }
+        }
+        Some(GameModeState::ChainLink(teams)) => {
+            for (team, state) in teams {
+                push(*team, &|r| {
+                    r.tickets = Some(state.tickets);
+                    r.tickets_max = Some(state.tickets_max);
+                });
+            }
+        }
+        Some(GameModeState::AirSuperiority(teams)) => {
+            for (team, state) in teams {
+                push(*team, &|r| {
+                    r.tickets = Some(state.tickets);
+                    r.tickets_max = Some(state.tickets_max);
+                });
+            }
+        }
+        Some(GameModeState::Deathmatch(teams)) | Some(GameModeState::SquadDeathmatch(teams)) => {
+            for (team, state) in teams {
+                push(*team, &|r| {
+                    r.kills = Some(state.kills);
+                    r.kills_max = Some(state.kills_max);
+                });
+            }
+        }
+        Some(GameModeState::CarrierAssault(teams)) => {
+            for (team, state) in teams {
+                push(*team, &|r| {
+                    r.destroyed_crates = Some(state.destroyed_crates);
+                    r.carrier_health = Some(state.carrier_health);
+                });
+            }
+        }
+        Some(GameModeState::Obliteration(teams)) | Some(GameModeState::SquadObliteration(teams)) => {
+            for (team, state) in teams {
+                push(*team, &|r| {
+                    r.score = Some(state.score);
+                    r.score_max = Some(state.score_max);
+                });
+            }
+        }
+        Some(GameModeState::CaptureTheFlag(teams)) => {
+            for (team, state) in teams {
+                push(*team, &|r| {
+                    r.flags = Some(state.flags);
+                    r.flags_max = Some(state.flags_max);
+                });
+            }
+        }
+        Some(GameModeState::Defuse(teams)) => {
+            for (team, state) in teams {
+                push(*team, &|r| {
+                    r.rounds = Some(state.rounds);
+                    r.rounds_max = Some(state.rounds_max);
+                });
+            }
+        }
+        None => {}
+    }
+
+    readings
+}
+
+/// Written into the `round_summary` measurement when a round ends.
+#[derive(Debug, Clone, Serialize, Deserialize, InfluxDbWriteable)]
+pub struct RoundSummaryReading {
+    pub time: DateTime<Utc>,
+    #[influxdb(tag)]
+    pub server_guid: String,
+    #[influxdb(tag)]
+    pub game_mode: String,
+    #[influxdb(tag)]
+    pub current_map: String,
+    pub game_mode_name: String,
+    pub current_map_name: String,
+    pub game_id: u64,
+    pub started_at: i64,
+    pub duration: u32,
+    pub winner: Option<u8>,
+    pub team1_score: Option<u32>,
+    pub team2_score: Option<u32>,
+    /// Every team's final score, formatted as `team:score` pairs.
+    pub scores: String,
+    pub peak_players: u16,
+    /// The best players of the round, formatted as `name:score` pairs.
+    pub top_scorers: String,
+    /// From 0 to 1, how far the round got before it ended
+    pub progress: Option<f64>,
+}
+
+impl RoundSummaryReading {
+    pub fn new(server_guid: &str, summary: &RoundSummary) -> Self {
+        let game_mode = GameMode::from(summary.game_mode.as_str());
+        let map = Map::from(summary.current_map.as_str());
+
+        Self {
+            time: summary.ended_at,
+            server_guid: server_guid.to_string(),
+            game_mode: summary.game_mode.to_string(),
+            current_map: map.id().to_string(),
+            game_mode_name: game_mode.name().to_string(),
+            current_map_name: map.name().to_string(),
+            game_id: summary.game_id,
+            started_at: summary.started_at.timestamp(),
+            duration: summary.duration,
+            winner: summary.winner,
+            team1_score: summary.scores.get(&1).copied(),
+            team2_score: summary.scores.get(&2).copied(),
+            scores: summary.scores
+                .iter()
+                .map(|(team, score)| format!("{}:{}", team, score))
+                .collect::<Vec<_>>()
+                .join(","),
+            peak_players: summary.peak_players,
+            top_scorers: summary.top_scorers
+                .iter()
+                .map(|(name, score)| format!("{}:{}", name, score))
+                .collect::<Vec<_>>()
+                .join(","),
+            progress: summary.progress,
+        }
+    }
+}
+
+/// Written into the `round_event` measurement for every [`RoundEvent`].
+#[derive(Debug, Clone, Serialize, Deserialize, InfluxDbWriteable)]
+pub struct RoundEventReading {
+    pub time: DateTime<Utc>,
+    #[influxdb(tag)]
+    pub server_guid: String,
+    #[influxdb(tag)]
+    pub event: String,
+    pub game_id: u64,
+    pub current_map: String,
+    pub previous_map: Option<String>,
+    pub winner: Option<u8>,
+}
+
+impl RoundEventReading {
+    pub fn new(time: DateTime<Utc>, server_guid: &str, snapshot: &Snapshot, event: &RoundEvent) -> Self {
+        let mut reading = Self {
+            time,
+            server_guid: server_guid.to_string(),
+            event: String::new(),
+            game_id: snapshot.game_id,
+            current_map: snapshot.get_map().id().to_string(),
+            previous_map: None,
+            winner: None,
+        };
+
+        match event {
+            RoundEvent::Started { .. } => reading.event = "round_start".to_string(),
+            RoundEvent::Ended(summary) => {
+                reading.event = "round_end".to_string();
+                reading.game_id = summary.game_id;
+                reading.current_map = Map::from(summary.current_map.as_str()).id().to_string();
+                reading.winner = summary.winner;
+            }
+            RoundEvent::MapChanged { from, .. } => {
+                reading.event = "map_change".to_string();
+                reading.previous_map = Some(Map::from(from.as_str()).id().to_string());
+            }
+        }
+
+        reading
+    }
+}
+
+/// Written into the `session_event` measurement for every [`SessionEvent`].
+#[derive(Debug, Clone, Serialize, Deserialize, InfluxDbWriteable)]
+pub struct SessionEventReading {
+    pub time: DateTime<Utc>,
+    #[influxdb(tag)]
+    pub server_guid: String,
+    #[influxdb(tag)]
+    pub event: String,
+    #[influxdb(tag)]
+    pub persona_id: u64,
+    #[influxdb(tag)]
+    pub name: String,
+    pub team: u8,
+    pub previous_team: Option<u8>,
+    pub clan_tag: Option<String>,
+    pub joined_at: Option<i64>,
+    /// When the player was last seen, the leave event is written a poll later
+    pub left_at: Option<i64>,
+    /// Session length in seconds
+    pub duration: Option<i64>,
+    pub team_switches: Option<u32>,
+    pub score: Option<u32>,
+    pub kills: Option<u32>,
+    pub deaths: Option<u32>,
+}
+
+impl SessionEventReading {
+    pub fn new(time: DateTime<Utc>, server_guid: &str, event: &SessionEvent) -> Self {
+        let (event_name, persona_id, name, team) = match event {
+            SessionEvent::Joined { persona_id, name, team } => ("join", *persona_id, name, *team),
+            SessionEvent::SwitchedTeam { persona_id, name, to, .. } => ("team_switch", *persona_id, name, *to),
+            SessionEvent::Left(session) => ("leave", session.persona_id, &session.name, session.team),
+        };
+
+        let mut reading = Self {
+            time,
+            server_guid: server_guid.to_string(),
+            event: event_name.to_string(),
+            persona_id,
+            name: name.to_string(),
+            team,
+            previous_team: None,
+            clan_tag: None,
+            joined_at: None,
+            left_at: None,
+            duration: None,
+            team_switches: None,
+            score: None,
+            kills: None,
+            deaths: None,
+        };
+
+        match event {
+            SessionEvent::Joined { .. } => {}
+            SessionEvent::SwitchedTeam { from, .. } => reading.previous_team = Some(*from),
+            SessionEvent::Left(session) => {
+                reading.clan_tag = Some(session.clan_tag.to_string()).filter(|tag| !tag.is_empty());
+                reading.joined_at = Some(session.joined_at.timestamp());
+                reading.left_at = Some(session.left_at.timestamp());
+                reading.duration = Some(session.duration());
+                reading.team_switches = Some(session.team_switches);
+                reading.score = Some(session.score);
+                reading.kills = Some(session.kills);
+                reading.deaths = Some(session.deaths);
+            }
+        }
+
+        reading
+    }
+}
+
+/// Written into the `poll_status` measurement for every poll of a server, failed or not.
+#[derive(Debug, Clone, Serialize, Deserialize, InfluxDbWriteable)]
+pub struct PollStatusReading {
+    pub time: DateTime<Utc>,
+    #[influxdb(tag)]
+    pub server_guid: String,
+    /// One of success, http_error, decode_error or timeout
+    #[influxdb(tag)]
+    pub status: String,
+    /// Time until the snapshot was received or the request failed
+    pub latency_ms: u64,
+    /// Failed polls in a row, zero after a success
+    pub consecutive_failures: u32,
+    pub error: Option<String>,
+}
+
+impl PollStatusReading {
+    pub fn new(
+        time: DateTime<Utc>,
+        server_guid: &str,
+        status: PollStatus,
+        latency: Duration,
+        consecutive_failures: u32,
+        error: Option<String>,
+    ) -> Self {
+        Self {
+            time,
+            server_guid: server_guid.to_string(),
+            status: status.to_string(),
+            latency_ms: latency.as_millis() as u64,
+            consecutive_failures,
+            error,
+        }
+    }
+}
+
+/// A map of the rotation configured on the server, written into the `rotation` measurement
+/// whenever the rotation on its Battlelog page changes.
+///
+/// Every entry of a rotation gets the same time, so the latest rotation is the one with the
+/// latest time.
+#[derive(Debug, Clone, Serialize, Deserialize, InfluxDbWriteable)]
+pub struct RotationReading {
+    pub time: DateTime<Utc>,
+    #[influxdb(tag)]
+    pub server_guid: String,
+    /// Place in the rotation, starting from 0
+    #[influxdb(tag)]
+    pub position: u32,
+    pub current_map: String,
+    pub current_map_name: String,
+    pub game_mode: String,
+    pub game_mode_name: String,
+}
+
+pub fn rotation_readings(time: DateTime<Utc>, server_guid: &str, rotation: &[RotationEntry]) -> Vec<RotationReading> {
+    rotation
+        .iter()
+        .enumerate()
+        .map(|(position, entry)| RotationReading {
+            time,
+            server_guid: server_guid.to_string(),
+            position: position as u32,
+            current_map: entry.map.id().to_string(),
+            current_map_name: entry.map.name().to_string(),
+            game_mode: entry.game_mode.id().to_string(),
+            game_mode_name: entry.game_mode.name().to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_tags() {
+        let mut server = ServerConfig {
+            guid: "4d0151b3-81ff-4268-b4e8-5e60d5bc8765".to_string(),
+            label: Some("BattleFox #1".to_string()),
+            interval: 30000,
+            tags: BTreeMap::new(),
+            measurements: Default::default(),
+            sinks: Vec::new(),
+        };
+        server.tags.insert("region".to_string(), "eu".to_string());
+
+        let batch = RecordBatch::new(&server, Some("=BFX= BattleFox #1 | Locker 24/7"));
+        assert_eq!("BattleFox #1", batch.tags["server_label"]);
+        assert_eq!("eu", batch.tags["region"]);
+        // The Battlelog name is a field, it would split the series as a tag
+        assert!(!batch.tags.contains_key("server_name"));
+        assert_eq!(Some("=BFX= BattleFox #1 | Locker 24/7"), batch.server_name.as_deref());
+    }
+
+    #[test]
+    fn batch_to_json() {
+        let data = crate::fixture("snapshot_rush");
+        let mut tags = BTreeMap::new();
+        tags.insert("server_label".to_string(), "BattleFox #1".to_string());
+        tags.insert("region".to_string(), "eu".to_string());
+        let batch = RecordBatch {
+            tags,
+            server_name: Some("=BFX= BattleFox #1".to_string()),
+            records: vec![Record::Snapshot(SnapshotReading::new(Utc::now(), "guid", &data.snapshot))],
+        };
+
+        let objects = batch.to_json().unwrap();
+        let object = &objects[0];
+        assert_eq!(Some("measurement"), object.keys().next().map(String::as_str));
+        assert_eq!("snapshot", object["measurement"]);
+        assert_eq!("eu", object["region"]);
+        assert_eq!("BattleFox #1", object["server_label"]);
+        assert_eq!("=BFX= BattleFox #1", object["server_name"]);
+        assert_eq!(true, object["round_running"]);
+        assert!(object["defender_team"].is_u64());
+    }
+}
@@ -0,0 +1,223 @@
+//! Compares the map rotation configured on a server with the rounds that were actually played.
+//!
+//! A map counts as skipped when the server goes from one round to a map further down the
+//! rotation than the next one, for example after an admin ran `nextmap` or a vote. A round
+//! ended early when it got less than `min_progress` of the way to its end.
+//!
+//! Only the rounds played since the server switched to the rotation are compared with it, the
+//! earlier ones were played with another rotation.
+
+use battlelog::{GameMode, Map};
+
+use crate::sinks::{MapPopulation, RotationRow, RoundRow};
+
+/// Longest time in seconds between two rounds that still counts as one following the other.
+/// Past it the logger or the server was probably down, and the rounds in between are unknown.
+const MAX_ROUND_GAP: i64 = 15 * 60;
+
+/// How a map of the rotation fared, see [`RotationReport`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapReport {
+    /// Place in the rotation, `None` for a map that was played but isn't in it
+    pub position: Option<u32>,
+    pub current_map: Map,
+    pub game_mode: GameMode,
+    /// Rounds played on the map
+    pub played: u32,
+    /// Times the server moved past the map without playing it
+    pub skipped: u32,
+    /// Rounds that ended before `min_progress`
+    pub ended_early: u32,
+    /// Average player count while the map was played, `None` without snapshots
+    pub average_players: Option<f64>,
+}
+
+/// One line per map of the rotation, followed by the maps that were played outside of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RotationReport {
+    pub maps: Vec<MapReport>,
+}
+
+impl RotationReport {
+    /// `rounds` are oldest first, `rotation` in the order the maps are played. The rounds that
+    /// started before the rotation was recorded are left out.
+    pub fn new(rotation: &[RotationRow], rounds: &[RoundRow], population: &[MapPopulation], min_progress: f64) -> Self {
+        let mut maps: Vec<MapReport> = rotation
+            .iter()
+            .map(|entry| MapReport {
+                position: Some(entry.position),
+                current_map: Map::from(entry.current_map.as_str()),
+                game_mode: GameMode::from(entry.game_mode.as_str()),
+                played: 0,
+                skipped: 0,
+                ended_early: 0,
+                average_players: None,
+            })
+            .collect();
+        let len = rotation.len();
+
+        // Where the server is in the rotation, unknown until a round of it is seen
+        let mut current: Option<usize> = None;
+        let mut previous: Option<&RoundRow> = None;
+        let since = rotation.iter().map(|entry| entry.time).max();
+        for round in rounds.iter().filter(|round| since.is_none_or(|since| round.started_at >= since)) {
+            let map = Map::from(round.current_map.as_str());
+            let game_mode = GameMode::from(round.game_mode.as_str());
+            let follows = previous.is_some_and(|previous| (round.started_at - previous.ended_at).num_seconds() <= MAX_ROUND_GAP);
+            previous = Some(round);
+
+            // The nearest place of the map from the current one on, the same place for a restart
+            let found = match current {
+                Some(current) => (0..len).map(|step| (current + step) % len).find(|&i| maps[i].is(&map, &game_mode)),
+                None => maps[..len].iter().position(|report| report.is(&map, &game_mode)),
+            };
+            if let (Some(from), Some(to), true) = (current, found, follows) {
+                let mut position = (from + 1) % len;
+                while from != to && position != to {
+                    maps[position].skipped += 1;
+                    position = (position + 1) % len;
+                }
+            }
+            current = found;
+
+            let index = match found {
+                Some(index) => index,
+                None => match maps[len..].iter().position(|report| report.is(&map, &game_mode)) {
+                    Some(index) => len + index,
+                    None => {
+                        maps.push(MapReport {
+                            position: None,
+                            current_map: map,
+                            game_mode,
+                            played: 0,
+                            skipped: 0,
+                            ended_early: 0,
+                            average_players: None,
+                        });
+                        maps.len() - 1
+                    }
+                },
+            };
+            maps[index].played += 1;
+            if round.progress.is_some_and(|progress| progress < min_progress) {
+                maps[index].ended_early += 1;
+            }
+        }
+
+        for report in &mut maps {
+            report.average_players = population
+                .iter()
+                .find(|population| {
+                    report.is(&Map::from(population.current_map.as_str()), &GameMode::from(population.game_mode.as_str()))
+                })
+                .map(|population| population.average);
+        }
+
+        Self { maps }
+    }
+}
+
+impl MapReport {
+    fn is(&self, map: &Map, game_mode: &GameMode) -> bool {
+        self.current_map == *map && self.game_mode == *game_mode
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Duration, Utc};
+
+    fn rotation(time: DateTime<Utc>, maps: &[&str]) -> Vec<RotationRow> {
+        maps.iter()
+            .enumerate()
+            .map(|(position, map)| RotationRow {
+                time,
+                position: position as u32,
+                current_map: map.to_string(),
+                game_mode: "ConquestLarge0".to_string(),
+            })
+            .collect()
+    }
+
+    fn round(started_at: DateTime<Utc>, map: &str, progress: f64) -> RoundRow {
+        RoundRow {
+            started_at,
+            ended_at: started_at + Duration::minutes(30),
+            current_map: map.to_string(),
+            game_mode: "ConquestLarge0".to_string(),
+            duration: 1800,
+            winner: Some(1),
+            scores: "1:100,2:0".to_string(),
+            peak_players: 64,
+            progress: Some(progress),
+        }
+    }
+
+    #[test]
+    fn skipped_and_ended_early() {
+        let start = Utc::now() - Duration::days(1);
+        let rotation = rotation(start, &["MP_Prison", "MP_Abandoned", "MP_Damage", "MP_Flooded"]);
+        let next = |n: i64| start + Duration::minutes(32 * n);
+        let rounds = vec![
+            // Played with the rotation before this one
+            round(next(-2), "MP_Siege", 1.0),
+            round(next(-1), "MP_Abandoned", 1.0),
+            round(next(0), "MP_Prison", 1.0),
+            // Zavod is voted off half way, Lancang Dam is skipped for Flood Zone
+            round(next(1), "MP_Abandoned", 0.4),
+            round(next(2), "MP_Flooded", 1.0),
+            // Around the end of the rotation, and a restart of Locker
+            round(next(3), "MP_Prison", 1.0),
+            round(next(4), "MP_Prison", 0.1),
+            // After the logger was down nothing counts as skipped
+            round(next(10), "MP_Flooded", 1.0),
+            round(next(11), "MP_Naval", 1.0),
+        ];
+        let population = vec![MapPopulation {
+            current_map: "MP_Prison".to_string(),
+            game_mode: "ConquestLarge0".to_string(),
+            average: 58.5,
+        }];
+
+        let report = RotationReport::new(&rotation, &rounds, &population, 0.9);
+        let summary: Vec<_> = report
+            .maps
+            .iter()
+            .map(|map| (map.position, map.current_map.id().to_string(), map.played, map.skipped, map.ended_early))
+            .collect();
+        assert_eq!(
+            vec![
+                (Some(0), "MP_Prison".to_string(), 3, 0, 1),
+                (Some(1), "MP_Abandoned".to_string(), 1, 0, 1),
+                (Some(2), "MP_Damage".to_string(), 0, 1, 0),
+                (Some(3), "MP_Flooded".to_string(), 2, 0, 0),
+                (None, "MP_Naval".to_string(), 1, 0, 0),
+            ],
+            summary
+        );
+        assert_eq!(Some(58.5), report.maps[0].average_players);
+        assert_eq!(None, report.maps[1].average_players);
+    }
+
+    #[test]
+    fn repeated_map() {
+        // The same map twice in the rotation, each place is tracked on its own. Going from the
+        // second Locker back to Zavod skips Lancang Dam and the first Locker.
+        let start = Utc::now() - Duration::days(1);
+        let rotation = rotation(start, &["MP_Prison", "MP_Abandoned", "MP_Prison", "MP_Damage"]);
+        let next = |n: i64| start + Duration::minutes(32 * n);
+        let rounds = vec![
+            round(next(0), "MP_Abandoned", 1.0),
+            round(next(1), "MP_Prison", 1.0),
+            round(next(2), "MP_Prison", 1.0),
+            round(next(3), "MP_Abandoned", 1.0),
+        ];
+
+        let report = RotationReport::new(&rotation, &rounds, &[], 0.9);
+        let skipped: Vec<u32> = report.maps.iter().map(|map| map.skipped).collect();
+        let played: Vec<u32> = report.maps.iter().map(|map| map.played).collect();
+        assert_eq!(vec![1, 0, 0, 1], skipped);
+        assert_eq!(vec![0, 2, 2, 0], played);
+    }
+}
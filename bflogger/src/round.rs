@@ -0,0 +1,378 @@
+use std::collections::BTreeMap;
+
+use battlelog::{GameModeState, Snapshot};
+use chrono::{DateTime, Utc};
+
+/// How many players are listed in [`RoundSummary::top_scorers`].
+const TOP_SCORERS: usize = 3;
+
+/// Round time in seconds past which the first round the tracker sees counts as joined midway.
+const PARTIAL_ROUND_TIME: u32 = 120;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RoundEvent {
+    Started {
+        game_id: u64,
+        current_map: String,
+        game_mode: String,
+    },
+    Ended(RoundSummary),
+    MapChanged {
+        from: String,
+        to: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoundSummary {
+    pub game_id: u64,
+    pub current_map: String,
+    pub game_mode: String,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    /// Round time in seconds, as reported by the server on the last poll of the round.
+    pub duration: u32,
+    /// Final score of each team, see [`team_scores`].
+    pub scores: BTreeMap<u8, u32>,
+    pub winner: Option<u8>,
+    pub peak_players: u16,
+    /// Name and score of the best players, best first.
+    pub top_scorers: Vec<(String, u32)>,
+    /// How far the round got on its last poll, see [`round_progress`]. Well short of 1 means
+    /// the round was cut off, by an admin or the server going down.
+    pub progress: Option<f64>,
+    /// The tracker joined the round midway, so `started_at`, the peak and the scores only
+    /// cover part of it.
+    pub partial: bool,
+}
+
+/// State of the round in progress, taken from the last snapshot that had a game mode block.
+#[derive(Debug, Clone)]
+struct Round {
+    game_id: u64,
+    current_map: String,
+    game_mode: String,
+    started_at: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    round_time: u32,
+    scores: BTreeMap<u8, u32>,
+    winner: Option<u8>,
+    peak_players: u16,
+    top_scorers: Vec<(String, u32)>,
+    progress: Option<f64>,
+    partial: bool,
+}
+
+impl Round {
+    fn new(time: DateTime<Utc>, snapshot: &Snapshot, partial: bool) -> Self {
+        let mut round = Self {
+            game_id: snapshot.game_id,
+            current_map: snapshot.current_map.to_string(),
+            game_mode: snapshot.game_mode.to_string(),
+            started_at: time,
+            last_seen: time,
+            round_time: 0,
+            scores: BTreeMap::new(),
+            winner: None,
+            peak_players: 0,
+            top_scorers: Vec::new(),
+            progress: None,
+            partial,
+        };
+        round.update(time, snapshot);
+        round
+    }
+
+    fn update(&mut self, time: DateTime<Utc>, snapshot: &Snapshot) {
+        self.last_seen = time;
+        self.round_time = snapshot.round_time;
+        self.scores = team_scores(snapshot);
+        self.winner = leading_team(snapshot);
+        self.peak_players = self.peak_players.max(snapshot.get_players_count());
+        self.top_scorers = top_scorers(snapshot);
+        self.progress = round_progress(snapshot);
+    }
+
+    /// Whether the snapshot is still from this round.
+    fn is_same_round(&self, snapshot: &Snapshot) -> bool {
+        self.game_id == snapshot.game_id
+            && self.current_map == snapshot.current_map
+            && self.game_mode == snapshot.game_mode
+            // The round time starts over when the round is restarted on the same map
+            && self.round_time <= snapshot.round_time
+    }
+
+    fn into_summary(self) -> RoundSummary {
+        RoundSummary {
+            game_id: self.game_id,
+            current_map: self.current_map,
+            game_mode: self.game_mode,
+            started_at: self.started_at,
+            ended_at: self.last_seen,
+            duration: self.round_time,
+            scores: self.scores,
+            winner: self.winner,
+            peak_players: self.peak_players,
+            top_scorers: self.top_scorers,
+            progress: self.progress,
+            partial: self.partial,
+        }
+    }
+}
+
+/// Watches consecutive snapshots of a single server and detects the round lifecycle.
+#[derive(Debug, Default)]
+pub struct RoundTracker {
+    round: Option<Round>,
+    last_map: Option<String>,
+}
+
+impl RoundTracker {
+    /// Feeds the next snapshot of the server and returns what happened since the previous one.
+    pub fn update(&mut self, time: DateTime<Utc>, snapshot: &Snapshot) -> Vec<RoundEvent> {
+        let mut events = Vec::new();
+        // The game mode block is missing between rounds
+        let running = snapshot.game_mode_state().is_some();
+
+        if let Some(round) = &mut self.round {
+            if running && round.is_same_round(snapshot) {
+                round.update(time, snapshot);
+                return events;
+            }
+        }
+
+        if let Some(round) = self.round.take() {
+            events.push(RoundEvent::Ended(round.into_summary()));
+        }
+
+        // The first round seen may have been going on for a while already
+        let first = self.last_map.is_none();
+        if let Some(last_map) = &self.last_map {
+            if *last_map != snapshot.current_map {
+                events.push(RoundEvent::MapChanged {
+                    from: last_map.to_string(),
+                    to: snapshot.current_map.to_string(),
+                });
+            }
+        }
+        self.last_map = Some(snapshot.current_map.to_string());
+
+        if running {
+            let partial = first && snapshot.round_time > PARTIAL_ROUND_TIME;
+            self.round = Some(Round::new(time, snapshot, partial));
+            events.push(RoundEvent::Started {
+                game_id: snapshot.game_id,
+                current_map: snapshot.current_map.to_string(),
+                game_mode: snapshot.game_mode.to_string(),
+            });
+        }
+
+        events
+    }
+}
+
+/// The primary score of each team in the current game mode, where higher is better.
+///
+/// That's tickets for Conquest like modes, kills for Deathmatch, remaining carrier health for
+/// Carrier Assault and so on. For Rush the attackers have their tickets and defenders the
+/// remaining bases.
+pub fn team_scores(snapshot: &Snapshot) -> BTreeMap<u8, u32> {
+    fn collect<T>(teams: &std::collections::HashMap<u8, T>, score: impl Fn(&T) -> u32) -> BTreeMap<u8, u32> {
+        teams.iter().map(|(team, state)| (*team, score(state))).collect()
+    }
+
+    match snapshot.game_mode_state() {
+        Some(GameModeState::Rush(rush)) => {
+            let mut scores = BTreeMap::new();
+            scores.insert(rush.attackers.team, u32::from(rush.attackers.tickets));
+            scores.insert(rush.defenders.team, u32::from(rush.defenders.bases));
+            scores
+        }
+        Some(GameModeState::Conquest(teams)) => collect(teams, |t| t.tickets),
+        Some(GameModeState::Domination(teams)) => collect(teams, |t| t.tickets),
+        Some(GameModeState::ChainLink(teams)) => collect(teams, |t| t.tickets),
+        Some(GameModeState::AirSuperiority(teams)) => collect(teams, |t| t.tickets),
+        Some(GameModeState::Deathmatch(teams)) | Some(GameModeState::SquadDeathmatch(teams)) => collect(teams, |t| t.kills),
+        Some(GameModeState::CarrierAssault(teams)) => collect(teams, |t| u32::from(t.carrier_health)),
+        Some(GameModeState::Obliteration(teams)) | Some(GameModeState::SquadObliteration(teams)) => collect(teams, |t| t.score),
+        Some(GameModeState::CaptureTheFlag(teams)) => collect(teams, |t| u32::from(t.flags)),
+        Some(GameModeState::Defuse(teams)) => collect(teams, |t| u32::from(t.rounds)),
+        None => BTreeMap::new(),
+    }
+}
+
+/// How much of the round is over, from 0 at the start to 1 when it ends, `None` between rounds.
+///
+/// Taken from the team that's closest to ending the round, like the one with the fewest
+/// tickets in Conquest or the most kills in Team Deathmatch.
+pub fn round_progress(snapshot: &Snapshot) -> Option<f64> {
+    fn ratio(value: impl Into<f64>, max: impl Into<f64>) -> Option<f64> {
+        let max = max.into();
+        if max > 0.0 {
+            Some((value.into() / max).clamp(0.0, 1.0))
+        } else {
+            None
+        }
+    }
+    /// Progress of the team closest to the end, for scores that count up to the max
+    fn most<T>(teams: &std::collections::HashMap<u8, T>, progress: impl Fn(&T) -> Option<f64>) -> Option<f64> {
+        teams.values().filter_map(progress).reduce(f64::max)
+    }
+
+    match snapshot.game_mode_state()? {
+        GameModeState::Rush(rush) => {
+            let tickets = ratio(rush.attackers.tickets, rush.attackers.tickets_max).map(|left| 1.0 - left);
+            let bases = ratio(rush.defenders.bases, rush.defenders.bases_max).map(|left| 1.0 - left);
+            tickets.into_iter().chain(bases).reduce(f64::max)
+        }
+        GameModeState::Conquest(teams) => most(teams, |t| ratio(t.tickets, t.tickets_max).map(|left| 1.0 - left)),
+        GameModeState::Domination(teams) => most(teams, |t| ratio(t.tickets, t.tickets_max).map(|left| 1.0 - left)),
+        GameModeState::ChainLink(teams) => most(teams, |t| ratio(t.tickets, t.tickets_max).map(|left| 1.0 - left)),
+        GameModeState::AirSuperiority(teams) => most(teams, |t| ratio(t.tickets, t.tickets_max).map(|left| 1.0 - left)),
+        GameModeState::Deathmatch(teams) | GameModeState::SquadDeathmatch(teams) => most(teams, |t| ratio(t.kills, t.kills_max)),
+        // The carrier health is a percentage
+        GameModeState::CarrierAssault(teams) => most(teams, |t| ratio(t.carrier_health, 100).map(|left| 1.0 - left)),
+        GameModeState::Obliteration(teams) | GameModeState::SquadObliteration(teams) => most(teams, |t| ratio(t.score, t.score_max)),
+        GameModeState::CaptureTheFlag(teams) => most(teams, |t| ratio(t.flags, t.flags_max)),
+        GameModeState::Defuse(teams) => most(teams, |t| ratio(t.rounds, t.rounds_max)),
+    }
+}
+
+/// The team that would win if the round ended right now, `None` on a tie.
+fn leading_team(snapshot: &Snapshot) -> Option<u8> {
+    if let Some(GameModeState::Rush(rush)) = snapshot.game_mode_state() {
+        // Attackers win by taking every base before running out of tickets, until either
+        // happens nobody is ahead
+        return if rush.defenders.bases == 0 {
+            Some(rush.attackers.team)
+        } else if rush.attackers.tickets == 0 {
+            Some(rush.defenders.team)
+        } else {
+            None
+        };
+    }
+
+    let scores = team_scores(snapshot);
+    let best = scores.values().max()?;
+    let mut leaders = scores.iter().filter(|(_, score)| *score == best);
+    match (leaders.next(), leaders.next()) {
+        (Some((team, _)), None) => Some(*team),
+        _ => None,
+    }
+}
+
+fn top_scorers(snapshot: &Snapshot) -> Vec<(String, u32)> {
+    let mut players: Vec<(String, u32)> = snapshot.team_info
+        .values()
+        .flat_map(|team_info| team_info.players.values())
+        .map(|player| (player.name.to_string(), player.score))
+        .collect();
+    players.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    players.truncate(TOP_SCORERS);
+    players
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use battlelog::Conquest;
+    use chrono::Duration;
+
+    fn conquest_snapshot() -> Snapshot {
+        let data = crate::fixture("snapshot_conquest");
+        data.snapshot
+    }
+
+    fn set_tickets(snapshot: &mut Snapshot, team: u8, tickets: u32) {
+        snapshot.conquest.as_mut().unwrap().insert(team, Conquest { tickets, tickets_max: 800 });
+    }
+
+    #[test]
+    fn round_lifecycle() {
+        let start = Utc::now();
+        let mut tracker = RoundTracker::default();
+        let mut snapshot = conquest_snapshot();
+
+        let events = tracker.update(start, &snapshot);
+        assert!(matches!(events.as_slice(), [RoundEvent::Started { .. }]));
+
+        snapshot.round_time += 600;
+        set_tickets(&mut snapshot, 1, 0);
+        set_tickets(&mut snapshot, 2, 120);
+        assert!(tracker.update(start + Duration::minutes(10), &snapshot).is_empty());
+
+        // Between rounds there is no game mode block
+        let mut between = snapshot.clone();
+        between.conquest = None;
+        let events = tracker.update(start + Duration::minutes(11), &between);
+        match events.as_slice() {
+            [RoundEvent::Ended(summary)] => {
+                assert_eq!(Some(2), summary.winner);
+                assert_eq!(1702, summary.duration);
+                assert_eq!(Some(&120), summary.scores.get(&2));
+                assert_eq!(2, summary.peak_players);
+                assert_eq!(("xfileFIN".to_string(), 3120), summary.top_scorers[0]);
+                assert_eq!(Some(1.0), summary.progress);
+                // The fixture is 18 minutes into the round when the tracker first sees it
+                assert!(summary.partial);
+            }
+            events => panic!("unexpected events {:?}", events),
+        }
+
+        // Next map
+        let mut next = conquest_snapshot();
+        next.game_id += 1;
+        next.round_time = 5;
+        next.current_map = "XP1/Levels/XP1_001/XP1_001".to_string();
+        let events = tracker.update(start + Duration::minutes(12), &next);
+        assert!(matches!(events.as_slice(), [RoundEvent::MapChanged { .. }, RoundEvent::Started { .. }]));
+
+        let mut between = next.clone();
+        between.conquest = None;
+        match tracker.update(start + Duration::minutes(40), &between).as_slice() {
+            [RoundEvent::Ended(summary)] => assert!(!summary.partial),
+            events => panic!("unexpected events {:?}", events),
+        }
+    }
+
+    #[test]
+    fn rush_winner() {
+        let data = crate::fixture("snapshot_rush");
+        let mut snapshot = data.snapshot;
+        // Bases and tickets left on both sides
+        assert_eq!(None, leading_team(&snapshot));
+
+        let rush = snapshot.rush.as_mut().unwrap();
+        rush.defenders.bases = 0;
+        assert_eq!(Some(1), leading_team(&snapshot));
+
+        let rush = snapshot.rush.as_mut().unwrap();
+        rush.defenders.bases = 1;
+        rush.attackers.tickets = 0;
+        assert_eq!(Some(2), leading_team(&snapshot));
+    }
+
+    #[test]
+    fn round_restart_on_same_map() {
+        let start = Utc::now();
+        let mut tracker = RoundTracker::default();
+        let snapshot = conquest_snapshot();
+        tracker.update(start, &snapshot);
+
+        let mut restarted = snapshot;
+        restarted.round_time = 10;
+        let events = tracker.update(start + Duration::minutes(1), &restarted);
+        assert!(matches!(events.as_slice(), [RoundEvent::Ended(_), RoundEvent::Started { .. }]));
+    }
+
+    #[test]
+    fn progress() {
+        let mut snapshot = conquest_snapshot();
+        set_tickets(&mut snapshot, 1, 600);
+        set_tickets(&mut snapshot, 2, 80);
+        assert_eq!(Some(0.9), round_progress(&snapshot));
+
+        snapshot.conquest = None;
+        assert_eq!(None, round_progress(&snapshot));
+    }
+}
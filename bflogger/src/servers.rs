@@ -0,0 +1,757 @@
+//! The poll loops of the servers, and the pool that starts, updates and stops them when the
+//! config file changes or the admin API asks for it.
+//!
+//! A server that stays in the config keeps its task and with it the state of the round and
+//! session trackers. Its new settings are picked up before the next poll.
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime},
+};
+
+use battlelog::{BattlelogClient, RotationEntry, ServerInfo, ServerSummary};
+use chrono::Utc;
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Serialize;
+use tokio::{sync::watch, task::JoinHandle, time::sleep};
+
+use crate::config::{Config, DiscoveryConfig, Measurement, PollingConfig, ServerConfig};
+use crate::metrics::Metrics;
+use crate::poll::{error_chain, PollOutcome, PollStatus, RateLimit, Scheduler};
+use crate::records::{
+    player_readings, rotation_readings, team_readings, PollStatusReading, Record, RecordBatch, RoundEventReading, RoundSummaryReading,
+    SessionEventReading, SnapshotReading,
+};
+use crate::round::{round_progress, RoundEvent, RoundTracker};
+use crate::session::SessionTracker;
+use crate::sinks::{Fanout, SinkQueue};
+use crate::supervisor::{supervise, Shutdown};
+
+/// How often the config file is checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+/// How often the details of a server are fetched again, the name and rotation rarely change.
+const DETAILS_REFRESH: Duration = Duration::from_secs(60 * 60);
+/// How soon a failed fetch of the details is retried.
+const DETAILS_RETRY: Duration = Duration::from_secs(5 * 60);
+/// Searches in a row a discovered server can be missing from before it's stopped. With a player
+/// filter a server drops out of the search whenever it empties out for a moment.
+const MISSED_SEARCHES: u32 = 3;
+
+/// What the poll loops of every server share.
+#[derive(Clone)]
+pub struct Context {
+    pub battlelog: BattlelogClient,
+    pub metrics: Arc<Metrics>,
+    pub queues: BTreeMap<String, Arc<SinkQueue>>,
+    /// A 429 from keeper holds back every server, not just the one that got it
+    pub rate_limit: Arc<RateLimit>,
+    pub polling: PollingConfig,
+}
+
+/// State that is kept between the polls of a single server.
+#[derive(Default)]
+struct Trackers {
+    rounds: RoundTracker,
+    sessions: SessionTracker,
+    details: Details,
+    /// Failed polls in a row
+    failures: u32,
+}
+
+/// The latest details of the server from its Battlelog page.
+#[derive(Default)]
+struct Details {
+    info: Option<ServerInfo>,
+    /// When to fetch them again, right away if unset
+    next_fetch: Option<Instant>,
+    /// Whether the rotation is new or changed since it was last written
+    rotation_changed: bool,
+}
+
+impl Details {
+    fn is_due(&self) -> bool {
+        self.next_fetch.is_none_or(|next_fetch| Instant::now() >= next_fetch)
+    }
+
+    /// Keeps the previous details if the fetch failed.
+    fn update(&mut self, server_guid: &str, result: Result<ServerInfo, battlelog::Error>) {
+        match result {
+            Ok(info) => {
+                if self.info.as_ref().map(|old| &old.name) != Some(&info.name) {
+                    eprintln!("Server {} is named {:?}", server_guid, info.name);
+                }
+                if self.info.as_ref().map(|old| &old.rotation) != Some(&info.rotation) {
+                    eprintln!("Server {} has a rotation of {} maps", server_guid, info.rotation.len());
+                    self.rotation_changed = true;
+                }
+                self.info = Some(info);
+                self.next_fetch = Some(Instant::now() + DETAILS_REFRESH);
+            }
+            Err(err) => {
+                eprintln!("Error fetching the details of server {}: {}", server_guid, error_chain(&err));
+                self.next_fetch = Some(Instant::now() + DETAILS_RETRY);
+            }
+        }
+    }
+
+    fn name(&self) -> Option<&str> {
+        self.info.as_ref().map(|info| info.name.as_str())
+    }
+
+    /// The rotation if it hasn't been written since it changed, only once.
+    fn take_changed_rotation(&mut self) -> Option<&[RotationEntry]> {
+        if !std::mem::take(&mut self.rotation_changed) {
+            return None;
+        }
+        self.info.as_ref().map(|info| info.rotation.as_slice())
+    }
+}
+
+async fn log_new_entry(
+    battlelog: &BattlelogClient,
+    sinks: &Fanout,
+    metrics: &Metrics,
+    server: &ServerConfig,
+    trackers: &mut Trackers,
+) -> PollOutcome {
+    let server_guid = server.guid.as_str();
+    eprintln!("Logging new entry for server guid {}", &server_guid);
+
+    let started = Instant::now();
+    let result = battlelog.server_snapshot(server_guid).await;
+    let latency = started.elapsed();
+    let time = Utc::now();
+    let mut batch = RecordBatch::new(server, trackers.details.name());
+
+    let (status, error, outcome) = match &result {
+        Ok(data) => {
+            if trackers.failures > 0 {
+                eprintln!("Server {} is reporting again after {} failed polls", server_guid, trackers.failures);
+            }
+            trackers.failures = 0;
+            let outcome = PollOutcome::Success {
+                players: data.snapshot.get_players_count(),
+                round_progress: round_progress(&data.snapshot),
+            };
+            (PollStatus::Success, None, outcome)
+        }
+        Err(err) => {
+            trackers.failures += 1;
+            let status = PollStatus::from_error(err);
+            let error = error_chain(err);
+            eprintln!(
+                "Error polling server {} ({}, {} failed in a row): {}",
+                server_guid, status, trackers.failures, error
+            );
+            (status, Some(error), PollOutcome::failed(err, trackers.failures))
+        }
+    };
+    metrics.record_poll(server, status, trackers.failures);
+    if server.is_enabled(Measurement::PollStatus) {
+        batch.records.push(Record::PollStatus(PollStatusReading::new(
+            time,
+            server_guid,
+            status,
+            latency,
+            trackers.failures,
+            error,
+        )));
+    }
+    if let Some(rotation) = trackers.details.take_changed_rotation() {
+        if server.is_enabled(Measurement::Rotation) {
+            batch.records.extend(rotation_readings(time, server_guid, rotation).into_iter().map(Record::Rotation));
+        }
+    }
+
+    if let Ok(data) = &result {
+        metrics.update(server, time, &data.snapshot);
+
+        // The trackers see every snapshot, even if their measurements aren't written
+        let round_events = trackers.rounds.update(time, &data.snapshot);
+        let session_events = trackers.sessions.update(time, &data.snapshot);
+
+        if server.is_enabled(Measurement::Snapshot) {
+            batch.records.push(Record::Snapshot(SnapshotReading::new(time, server_guid, &data.snapshot)));
+        }
+        if server.is_enabled(Measurement::Team) {
+            batch.records.extend(team_readings(time, server_guid, &data.snapshot).into_iter().map(Record::Team));
+        }
+        if server.is_enabled(Measurement::Player) {
+            batch.records.extend(player_readings(time, server_guid, &data.snapshot).into_iter().map(Record::Player));
+        }
+        if server.is_enabled(Measurement::Round) {
+            batch.records.extend(
+                round_events
+                    .iter()
+                    .map(|event| Record::RoundEvent(RoundEventReading::new(time, server_guid, &data.snapshot, event))),
+            );
+            batch.records.extend(round_events.iter().filter_map(|event| match event {
+                // A round joined midway would be written with a wrong start and length
+                RoundEvent::Ended(summary) if !summary.partial => {
+                    Some(Record::RoundSummary(RoundSummaryReading::new(server_guid, summary)))
+                }
+                _ => None,
+            }));
+        }
+        if server.is_enabled(Measurement::Session) {
+            batch.records.extend(
+                session_events
+                    .iter()
+                    .map(|event| Record::SessionEvent(SessionEventReading::new(time, server_guid, event))),
+            );
+        }
+    }
+
+    sinks.write(batch);
+    outcome
+}
+
+async fn poll_loop(context: Context, mut updates: watch::Receiver<ServerConfig>, mut shutdown: Shutdown) {
+    let mut server = updates.borrow_and_update().clone();
+    eprintln!(
+        "Starting fetch loop for server guid {} ({}) with the interval of {}",
+        &server.guid,
+        server.label.as_deref().unwrap_or("no label"),
+        server.interval
+    );
+
+    let mut sinks = Fanout::new(&context.queues, &server.sinks);
+    let mut scheduler = Scheduler::new(server.interval, &context.polling);
+    let mut trackers = Trackers::default();
+    let mut rng = StdRng::from_entropy();
+    let mut delay = scheduler.initial_delay(&mut rng);
+
+    // Every await gives way to the shutdown, the batch is handed to the sinks without one
+    loop {
+        if shutdown.cancel(sleep(delay)).await.is_none() || shutdown.cancel(context.rate_limit.wait()).await.is_none() {
+            break;
+        }
+
+        if updates.has_changed().unwrap_or(false) {
+            server = updates.borrow_and_update().clone();
+            sinks = Fanout::new(&context.queues, &server.sinks);
+            scheduler = Scheduler::new(server.interval, &context.polling);
+            eprintln!("Updated the settings of server guid {}", server.guid);
+        }
+
+        if trackers.details.is_due() {
+            match shutdown.cancel(context.battlelog.server_info(&server.guid)).await {
+                Some(result) => {
+                    // Battlelog rate limits the page fetches like Keeper does the snapshots
+                    if let Err(err @ battlelog::Error::RateLimited { .. }) = &result {
+                        let outcome = PollOutcome::failed(err, 1);
+                        context.rate_limit.hold_off(scheduler.next_delay(&outcome, &mut rng));
+                    }
+                    trackers.details.update(&server.guid, result);
+                }
+                None => break,
+            }
+        }
+
+        let poll = log_new_entry(&context.battlelog, &sinks, &context.metrics, &server, &mut trackers);
+        let outcome = match shutdown.cancel(poll).await {
+            Some(outcome) => outcome,
+            None => break,
+        };
+
+        delay = scheduler.next_delay(&outcome, &mut rng);
+        if let PollOutcome::Failed { rate_limited: true, .. } = outcome {
+            context.rate_limit.hold_off(delay);
+        }
+    }
+
+    // The players still online leave with the server, before the queues are flushed on shutdown
+    let session_events = trackers.sessions.close();
+    if server.is_enabled(Measurement::Session) && !session_events.is_empty() {
+        let time = Utc::now();
+        let mut batch = RecordBatch::new(&server, trackers.details.name());
+        batch.records.extend(
+            session_events
+                .iter()
+                .map(|event| Record::SessionEvent(SessionEventReading::new(time, &server.guid, event))),
+        );
+        sinks.write(batch);
+    }
+}
+
+/// Where a running server came from. A reload of the config file only stops the servers that
+/// came from it, the ones added over the admin API stay until they're removed there and the
+/// discovered ones until a few searches of the server browser in a row miss them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    File,
+    Admin,
+    Discovery,
+}
+
+struct Running {
+    source: Source,
+    /// Searches in a row that didn't find the discovered server
+    missed: u32,
+    config: watch::Sender<ServerConfig>,
+    stop: watch::Sender<bool>,
+    task: JoinHandle<()>,
+}
+
+/// The GUIDs of the servers that a change started, updated and stopped.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Changes {
+    pub started: Vec<String>,
+    pub updated: Vec<String>,
+    pub stopped: Vec<String>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.started.is_empty() && self.updated.is_empty() && self.stopped.is_empty()
+    }
+}
+
+impl fmt::Display for Changes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "started [{}], updated [{}], stopped [{}]",
+            self.started.join(", "),
+            self.updated.join(", "),
+            self.stopped.join(", ")
+        )
+    }
+}
+
+/// A running server, as listed by the admin API.
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerStatus {
+    pub guid: String,
+    pub label: Option<String>,
+    pub interval: u64,
+    pub sinks: Vec<String>,
+    pub source: Source,
+}
+
+/// The servers being polled, keyed by the lowercase GUID.
+pub struct ServerPool {
+    context: Context,
+    /// The config the servers were last reconciled with, for the defaults of the admin API
+    config: Mutex<Config>,
+    running: Mutex<BTreeMap<String, Running>>,
+    /// Tasks of the stopped servers, which may still be writing their last batch
+    stopping: Mutex<Vec<JoinHandle<()>>>,
+    /// How many servers are running, updated after every change, `None` until one is started
+    count: watch::Sender<Option<usize>>,
+}
+
+impl ServerPool {
+    /// Starts the servers of the config.
+    pub fn start(context: Context, config: Config) -> Arc<Self> {
+        let servers = config.servers.clone();
+        let pool = Arc::new(Self {
+            context,
+            config: Mutex::new(config),
+            running: Mutex::new(BTreeMap::new()),
+            stopping: Mutex::new(Vec::new()),
+            count: watch::Sender::new(None),
+        });
+
+        let mut changes = Changes::default();
+        let mut running = pool.running();
+        for server in servers {
+            pool.start_or_update(&mut running, server, Source::File, &mut changes);
+        }
+        pool.set_count(running.len());
+        drop(running);
+        pool
+    }
+
+    /// Loads the config file again and starts, updates and stops the servers to match it.
+    ///
+    /// Only the servers are reloaded, the other settings need a restart.
+    pub fn reload(&self, path: Option<&Path>) -> Result<Changes, String> {
+        let config = Config::load(path).map_err(|err| err.to_string())?;
+        for server in &config.servers {
+            self.check_sinks(server)?;
+        }
+
+        let mut current = self.config();
+        if config.battlelog != current.battlelog
+            || config.metrics != current.metrics
+            || config.admin != current.admin
+            || config.discovery != current.discovery
+            || config.polling != current.polling
+            || config.queue != current.queue
+            || config.sinks != current.sinks
+        {
+            eprintln!("Only the servers of the changed config file are applied, restart for the other settings");
+        }
+        let added: Vec<&str> = config
+            .skipped_servers
+            .iter()
+            .filter(|guid| !current.skipped_servers.contains(guid))
+            .map(String::as_str)
+            .collect();
+        if !added.is_empty() {
+            eprintln!(
+                "Not starting the servers [{}] of the config file, SERVER_GUID picks the servers to log, unset it to log the ones of the file",
+                added.join(", ")
+            );
+        }
+
+        let mut changes = Changes::default();
+        let mut running = self.running();
+        for server in &config.servers {
+            self.start_or_update(&mut running, server.clone(), Source::File, &mut changes);
+        }
+        let removed: Vec<String> = running
+            .iter()
+            .filter(|(guid, server)| {
+                server.source == Source::File && !config.servers.iter().any(|s| s.guid.to_lowercase() == **guid)
+            })
+            .map(|(guid, _)| guid.to_string())
+            .collect();
+        for guid in removed {
+            if let Some(server) = running.remove(&guid) {
+                changes.stopped.push(self.stop(server));
+            }
+        }
+        self.set_count(running.len());
+
+        *current = config;
+        Ok(changes)
+    }
+
+    /// Starts or updates a server from the JSON version of its config file entry.
+    pub fn add(&self, guid: &str, json: &[u8]) -> Result<Changes, String> {
+        let server = self.config().server_from_json(guid, json).map_err(|err| err.to_string())?;
+        self.check_sinks(&server)?;
+
+        let mut changes = Changes::default();
+        let mut running = self.running();
+        self.start_or_update(&mut running, server, Source::Admin, &mut changes);
+        self.set_count(running.len());
+        Ok(changes)
+    }
+
+    /// Starts the servers that the discovery found and stops the discovered ones it hasn't found
+    /// for [`MISSED_SEARCHES`] searches.
+    pub fn discovered(&self, servers: &[ServerSummary]) -> Changes {
+        let configs: Vec<ServerConfig> = {
+            let config = self.config();
+            servers.iter().map(|summary| config.discovered_server(summary)).collect()
+        };
+
+        let mut changes = Changes::default();
+        let mut running = self.running();
+        for server in configs {
+            self.start_or_update(&mut running, server, Source::Discovery, &mut changes);
+        }
+        let mut missing = Vec::new();
+        for (guid, server) in running.iter_mut().filter(|(_, server)| server.source == Source::Discovery) {
+            if servers.iter().any(|s| s.guid.to_lowercase() == **guid) {
+                server.missed = 0;
+            } else {
+                server.missed += 1;
+                if server.missed >= MISSED_SEARCHES {
+                    missing.push(guid.to_string());
+                }
+            }
+        }
+        for guid in missing {
+            if let Some(server) = running.remove(&guid) {
+                changes.stopped.push(self.stop(server));
+            }
+        }
+        self.set_count(running.len());
+        changes
+    }
+
+    /// Stops a server, `None` if it isn't running.
+    pub fn remove(&self, guid: &str) -> Option<Changes> {
+        let mut running = self.running();
+        let server = running.remove(&guid.trim().to_lowercase())?;
+        self.set_count(running.len());
+        drop(running);
+        let guid = self.stop(server);
+        Some(Changes {
+            stopped: vec![guid],
+            ..Changes::default()
+        })
+    }
+
+    pub fn servers(&self) -> Vec<ServerStatus> {
+        self.running()
+            .values()
+            .map(|running| {
+                let server = running.config.borrow();
+                ServerStatus {
+                    guid: server.guid.to_string(),
+                    label: server.label.clone(),
+                    interval: server.interval,
+                    sinks: server.sinks.clone(),
+                    source: running.source,
+                }
+            })
+            .collect()
+    }
+
+    /// Waits until the servers that were running have all been stopped, which can't happen before
+    /// the first one is started.
+    pub async fn finished(&self) {
+        let _ = self.count.subscribe().wait_for(|count| *count == Some(0)).await;
+    }
+
+    /// Stops every server and waits for their tasks, also the ones of the servers stopped before,
+    /// for the shutdown.
+    pub async fn stop_all(&self) {
+        let running = std::mem::take(&mut *self.running());
+        self.set_count(0);
+        for (_, server) in running {
+            self.stop(server);
+        }
+        let stopping = std::mem::take(&mut *self.stopping.lock().expect("Server pool lock poisoned"));
+        for task in stopping {
+            let _ = task.await;
+        }
+    }
+
+    /// Tells the task of the server to stop, it still writes the sessions of the players that are
+    /// online. Returns the GUID.
+    fn stop(&self, server: Running) -> String {
+        let guid = server.config.borrow().guid.to_string();
+        eprintln!("Stopping the fetch loop for server guid {}", guid);
+        let _ = server.stop.send(true);
+        self.context.metrics.remove(&guid);
+
+        let mut stopping = self.stopping.lock().expect("Server pool lock poisoned");
+        stopping.retain(|task| !task.is_finished());
+        stopping.push(server.task);
+        guid
+    }
+
+    fn start_or_update(
+        &self,
+        running: &mut BTreeMap<String, Running>,
+        server: ServerConfig,
+        source: Source,
+        changes: &mut Changes,
+    ) {
+        let key = server.guid.to_lowercase();
+        if let Some(existing) = running.get_mut(&key) {
+            // The settings of a server in the config file or added over the API win over the
+            // defaults of a discovered one
+            if source == Source::Discovery && existing.source != Source::Discovery {
+                return;
+            }
+            // A server in the config file belongs to the file from now on, and a discovered one
+            // that was added explicitly stays
+            if source == Source::File || existing.source == Source::Discovery {
+                existing.source = source;
+            }
+            if *existing.config.borrow() != server {
+                changes.updated.push(server.guid.to_string());
+                existing.config.send_replace(server);
+            }
+            return;
+        }
+
+        changes.started.push(server.guid.to_string());
+        let name = format!("server {}", server.guid);
+        let (config, updates) = watch::channel(server);
+        let (stop, shutdown) = Shutdown::new();
+        let context = self.context.clone();
+        let task_shutdown = shutdown.clone();
+        let task = supervise(name, shutdown, move || {
+            poll_loop(context.clone(), updates.clone(), task_shutdown.clone())
+        });
+        running.insert(
+            key,
+            Running {
+                source,
+                missed: 0,
+                config,
+                stop,
+                task,
+            },
+        );
+    }
+
+    /// The sinks are only created on startup, so new ones need a restart.
+    fn check_sinks(&self, server: &ServerConfig) -> Result<(), String> {
+        match server.sinks.iter().find(|sink| !self.context.queues.contains_key(*sink)) {
+            Some(sink) => Err(format!(
+                "server {} writes to the sink {:?}, which isn't running, restart the logger to add sinks",
+                server.guid, sink
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn set_count(&self, running: usize) {
+        self.count.send_if_modified(|count| {
+            if (count.is_none() && running == 0) || *count == Some(running) {
+                return false;
+            }
+            *count = Some(running);
+            true
+        });
+    }
+
+    fn config(&self) -> MutexGuard<'_, Config> {
+        self.config.lock().expect("Server pool lock poisoned")
+    }
+
+    fn running(&self) -> MutexGuard<'_, BTreeMap<String, Running>> {
+        self.running.lock().expect("Server pool lock poisoned")
+    }
+}
+
+/// Reloads the servers whenever the modification time of the config file changes.
+pub async fn watch_config(pool: Arc<ServerPool>, path: PathBuf) {
+    let modified = |path: &Path| -> Option<SystemTime> { fs::metadata(path).and_then(|meta| meta.modified()).ok() };
+    let mut last = modified(&path);
+
+    loop {
+        sleep(WATCH_INTERVAL).await;
+        let current = modified(&path);
+        if current == last {
+            continue;
+        }
+        last = current;
+
+        match pool.reload(Some(&path)) {
+            Ok(changes) if changes.is_empty() => {}
+            Ok(changes) => eprintln!("Reloaded the servers of {}: {}", path.display(), changes),
+            Err(err) => eprintln!("Ignoring the changed config file {}: {}", path.display(), err),
+        }
+    }
+}
+
+/// Searches the server browser every interval and logs the servers it finds.
+///
+/// A failed search leaves the discovered servers running, they're only stopped once a search
+/// succeeds without them.
+pub async fn discover(pool: Arc<ServerPool>, battlelog: BattlelogClient, config: DiscoveryConfig) {
+    let query = match config.query() {
+        Ok(query) => query,
+        Err(err) => return eprintln!("Not discovering servers: {}", err),
+    };
+
+    loop {
+        match battlelog.server_browser(&query).await {
+            Ok(servers) => {
+                let changes = pool.discovered(&servers);
+                if !changes.is_empty() {
+                    eprintln!("Discovered {} matching servers: {}", servers.len(), changes);
+                }
+            }
+            Err(err) => eprintln!("Error searching the server browser: {}", error_chain(&err)),
+        }
+        sleep(Duration::from_millis(config.interval)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use battlelog::{GameMode, Map, Region};
+
+    use crate::config::QueueConfig;
+    use crate::sinks::StdoutSink;
+
+    const GUID: &str = "4d0151b3-81ff-4268-b4e8-5e60d5bc8765";
+    const OTHER_GUID: &str = "11111111-2222-3333-4444-555555555555";
+
+    fn write_config(path: &Path, servers: &[(&str, u64)]) {
+        let mut toml = "[admin]\nlisten = \"127.0.0.1:0\"\n[sinks.console]\ntype = \"stdout\"\n".to_string();
+        for (guid, interval) in servers {
+            toml.push_str(&format!("[[servers]]\nguid = \"{}\"\ninterval = {}\n", guid, interval));
+        }
+        fs::write(path, toml).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reconcile() {
+        let path = std::env::temp_dir().join(format!("bflogger-servers-{}.toml", std::process::id()));
+        write_config(&path, &[(GUID, 30000)]);
+
+        let metrics = Arc::new(Metrics::default());
+        let mut queues = BTreeMap::new();
+        let queue = SinkQueue::start("console", Arc::new(StdoutSink), &QueueConfig::default(), metrics.clone()).unwrap();
+        queues.insert("console".to_string(), queue);
+        let context = Context {
+            // Nothing listens there, the polls just fail
+            battlelog: BattlelogClient::builder().keeper_url("http://127.0.0.1:9").build().unwrap(),
+            metrics,
+            queues,
+            rate_limit: Arc::new(RateLimit::default()),
+            polling: PollingConfig::default(),
+        };
+        let pool = ServerPool::start(context, Config::load(Some(&path)).unwrap());
+
+        let changes = pool.add(OTHER_GUID, br#"{"label": "Added", "interval": 60000}"#).unwrap();
+        assert_eq!(vec![OTHER_GUID.to_string()], changes.started);
+        let err = pool.add(OTHER_GUID, br#"{"sinks": ["influxdb"]}"#).unwrap_err();
+        assert!(err.contains("\"influxdb\""), "{}", err);
+
+        // Emptying the file stops its server, the added one stays until it's removed
+        write_config(&path, &[]);
+        let changes = pool.reload(Some(&path)).unwrap();
+        assert_eq!(vec![GUID.to_string()], changes.stopped);
+        write_config(&path, &[(GUID, 15000), (OTHER_GUID, 60000)]);
+        let changes = pool.reload(Some(&path)).unwrap();
+        assert_eq!(vec![GUID.to_string()], changes.started);
+        assert_eq!(vec![OTHER_GUID.to_string()], changes.updated);
+        // Now that the file lists it, the added server goes away with it
+        write_config(&path, &[(GUID, 15000)]);
+        let changes = pool.reload(Some(&path)).unwrap();
+        assert_eq!(vec![OTHER_GUID.to_string()], changes.stopped);
+
+        let servers = pool.servers();
+        assert_eq!(1, servers.len());
+        assert_eq!(15000, servers[0].interval);
+        assert_eq!(Source::File, servers[0].source);
+
+        // A discovered server doesn't override the one in the file, and goes away once it's missing
+        let found = |guid: &str| ServerSummary {
+            guid: guid.to_string(),
+            name: "=BFX= BattleFox".to_string(),
+            region: Region::Europe,
+            country: "FI".to_string(),
+            map: Map::OperationLocker,
+            game_mode: GameMode::ConquestLarge,
+            players: 40,
+            max_players: 64,
+            queued: 0,
+            has_password: false,
+        };
+        let changes = pool.discovered(&[found(GUID), found(OTHER_GUID)]);
+        assert_eq!(vec![OTHER_GUID.to_string()], changes.started);
+        assert!(changes.updated.is_empty());
+        let servers = pool.servers();
+        assert_eq!(Some("=BFX= BattleFox".to_string()), servers[0].label);
+        assert_eq!(Source::Discovery, servers[0].source);
+        assert_eq!(Source::File, servers[1].source);
+        for _ in 1..MISSED_SEARCHES {
+            assert!(pool.discovered(&[]).is_empty());
+        }
+        // Found again, the count starts over
+        assert!(pool.discovered(&[found(OTHER_GUID)]).is_empty());
+        for _ in 1..MISSED_SEARCHES {
+            assert!(pool.discovered(&[]).is_empty());
+        }
+        let changes = pool.discovered(&[]);
+        assert_eq!(vec![OTHER_GUID.to_string()], changes.stopped);
+        assert_eq!(1, pool.servers().len());
+
+        assert!(pool.remove(OTHER_GUID).is_none());
+        assert!(pool.remove(&GUID.to_uppercase()).is_some());
+        // The last server is gone
+        tokio::time::timeout(Duration::from_secs(1), pool.finished()).await.unwrap();
+
+        pool.stop_all().await;
+        fs::remove_file(&path).unwrap();
+    }
+}
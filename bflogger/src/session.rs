@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+
+use battlelog::Snapshot;
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionEvent {
+    Joined {
+        persona_id: u64,
+        name: String,
+        team: u8,
+    },
+    SwitchedTeam {
+        persona_id: u64,
+        name: String,
+        from: u8,
+        to: u8,
+    },
+    Left(PlayerSession),
+}
+
+/// A player's stay on the server, from the first snapshot they were seen in to the last one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerSession {
+    pub persona_id: u64,
+    pub name: String,
+    pub clan_tag: String,
+    pub team: u8,
+    pub joined_at: DateTime<Utc>,
+    pub left_at: DateTime<Utc>,
+    pub team_switches: u32,
+    /// Score gained during the session, carried over the score resets between rounds.
+    pub score: u32,
+    pub kills: u32,
+    pub deaths: u32,
+}
+
+impl PlayerSession {
+    /// Session length in seconds.
+    pub fn duration(&self) -> i64 {
+        (self.left_at - self.joined_at).num_seconds()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Session {
+    session: PlayerSession,
+    // Scoreboard values of the last snapshot, used to detect the per-round resets
+    last_score: u32,
+    last_kills: u32,
+    last_deaths: u32,
+}
+
+impl Session {
+    fn accumulate(&mut self, score: u32, kills: u32, deaths: u32) {
+        // A lower value than before means that the scoreboard was reset for a new round
+        self.session.score += delta(self.last_score, score);
+        self.session.kills += delta(self.last_kills, kills);
+        self.session.deaths += delta(self.last_deaths, deaths);
+        self.last_score = score;
+        self.last_kills = kills;
+        self.last_deaths = deaths;
+    }
+}
+
+fn delta(last: u32, current: u32) -> u32 {
+    if current >= last {
+        current - last
+    } else {
+        current
+    }
+}
+
+/// Diffs the players of consecutive snapshots of a single server.
+#[derive(Debug, Default)]
+pub struct SessionTracker {
+    sessions: HashMap<u64, Session>,
+    initialized: bool,
+}
+
+impl SessionTracker {
+    /// Feeds the next snapshot of the server and returns the joins, leaves and team switches
+    /// since the previous one.
+    ///
+    /// The players of the very first snapshot are tracked without `Joined` events, since they
+    /// most likely joined before we started watching.
+    pub fn update(&mut self, time: DateTime<Utc>, snapshot: &Snapshot) -> Vec<SessionEvent> {
+        let mut events = Vec::new();
+        let initialized = self.initialized;
+        self.initialized = true;
+
+        for (team, team_info) in &snapshot.team_info {
+            for (persona_id, player) in &team_info.players {
+                match self.sessions.get_mut(persona_id) {
+                    Some(session) => {
+                        // Team 0 holds the players that are still joining or loading, so
+                        // moves into or out of it aren't team switches
+                        if *team != 0 && session.session.team == 0 {
+                            session.session.team = *team;
+                        } else if *team != 0 && session.session.team != *team {
+                            events.push(SessionEvent::SwitchedTeam {
+                                persona_id: *persona_id,
+                                name: player.name.to_string(),
+                                from: session.session.team,
+                                to: *team,
+                            });
+                            session.session.team = *team;
+                            session.session.team_switches += 1;
+                        }
+                        session.session.left_at = time;
+                        session.accumulate(player.score, player.kills, player.deaths);
+                    }
+                    None => {
+                        if initialized {
+                            events.push(SessionEvent::Joined {
+                                persona_id: *persona_id,
+                                name: player.name.to_string(),
+                                team: *team,
+                            });
+                        }
+                        self.sessions.insert(*persona_id, Session {
+                            session: PlayerSession {
+                                persona_id: *persona_id,
+                                name: player.name.to_string(),
+                                clan_tag: player.tag.to_string(),
+                                team: *team,
+                                joined_at: time,
+                                left_at: time,
+                                team_switches: 0,
+                                score: 0,
+                                kills: 0,
+                                deaths: 0,
+                            },
+                            last_score: player.score,
+                            last_kills: player.kills,
+                            last_deaths: player.deaths,
+                        });
+                    }
+                }
+            }
+        }
+
+        let left: Vec<u64> = self.sessions
+            .keys()
+            .filter(|persona_id| snapshot.get_player_by_personaid(**persona_id).is_none())
+            .copied()
+            .collect();
+        for persona_id in left {
+            if let Some(session) = self.sessions.remove(&persona_id) {
+                events.push(SessionEvent::Left(session.session));
+            }
+        }
+
+        events
+    }
+
+    /// Ends the sessions of the players that are still on the server, for when it stops being
+    /// logged. They leave when they were last seen.
+    pub fn close(&mut self) -> Vec<SessionEvent> {
+        self.initialized = false;
+        let mut sessions: Vec<PlayerSession> = self.sessions.drain().map(|(_, session)| session.session).collect();
+        sessions.sort_by_key(|session| session.persona_id);
+        sessions.into_iter().map(SessionEvent::Left).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn rush_snapshot() -> Snapshot {
+        let data = crate::fixture("snapshot_rush");
+        data.snapshot
+    }
+
+    #[test]
+    fn join_switch_and_leave() {
+        let start = Utc::now();
+        let mut tracker = SessionTracker::default();
+        let mut snapshot = rush_snapshot();
+
+        // Players that were already on the server aren't reported as joined
+        let pocketwolfy = snapshot.team_info.get_mut(&2).unwrap().players.remove(&994520424).unwrap();
+        assert!(tracker.update(start, &snapshot).is_empty());
+        assert_eq!(2, tracker.sessions.len());
+
+        snapshot.team_info.get_mut(&2).unwrap().players.insert(994520424, pocketwolfy);
+        let events = tracker.update(start + Duration::minutes(1), &snapshot);
+        assert!(matches!(events.as_slice(), [SessionEvent::Joined { persona_id: 994520424, team: 2, .. }]));
+
+        // Switch to the other team and gain some score
+        let mut player = snapshot.team_info.get_mut(&2).unwrap().players.remove(&994520424).unwrap();
+        player.score += 100;
+        snapshot.team_info.get_mut(&1).unwrap().players.insert(994520424, player);
+        let events = tracker.update(start + Duration::minutes(2), &snapshot);
+        assert!(matches!(events.as_slice(), [SessionEvent::SwitchedTeam { from: 2, to: 1, .. }]));
+
+        // New round resets the scoreboard
+        snapshot.team_info.get_mut(&1).unwrap().players.get_mut(&994520424).unwrap().score = 50;
+        tracker.update(start + Duration::minutes(3), &snapshot);
+
+        snapshot.team_info.get_mut(&1).unwrap().players.remove(&994520424);
+        let events = tracker.update(start + Duration::minutes(4), &snapshot);
+        match events.as_slice() {
+            [SessionEvent::Left(session)] => {
+                assert_eq!("PocketWolfy", session.name);
+                assert_eq!(1, session.team_switches);
+                assert_eq!(150, session.score);
+                assert_eq!(120, session.duration());
+            }
+            events => panic!("unexpected events {:?}", events),
+        }
+    }
+
+    #[test]
+    fn close_leaves_everyone() {
+        let start = Utc::now();
+        let mut tracker = SessionTracker::default();
+        let snapshot = rush_snapshot();
+        tracker.update(start, &snapshot);
+        tracker.update(start + Duration::minutes(1), &snapshot);
+
+        let events = tracker.close();
+        assert_eq!(snapshot.get_players_count() as usize, events.len());
+        for event in &events {
+            match event {
+                SessionEvent::Left(session) => assert_eq!(start + Duration::minutes(1), session.left_at),
+                event => panic!("unexpected event {:?}", event),
+            }
+        }
+        assert!(tracker.close().is_empty());
+    }
+
+    #[test]
+    fn loading_team_is_no_switch() {
+        let start = Utc::now();
+        let mut tracker = SessionTracker::default();
+        let mut snapshot = rush_snapshot();
+        let mut loading = snapshot.team_info[&1].clone();
+        loading.players.clear();
+        snapshot.team_info.insert(0, loading);
+
+        let pocketwolfy = snapshot.team_info.get_mut(&2).unwrap().players.remove(&994520424).unwrap();
+        tracker.update(start, &snapshot);
+
+        // Joins on the loading team, then gets placed on a real one
+        snapshot.team_info.get_mut(&0).unwrap().players.insert(994520424, pocketwolfy);
+        let events = tracker.update(start + Duration::minutes(1), &snapshot);
+        assert!(matches!(events.as_slice(), [SessionEvent::Joined { persona_id: 994520424, team: 0, .. }]));
+
+        let player = snapshot.team_info.get_mut(&0).unwrap().players.remove(&994520424).unwrap();
+        snapshot.team_info.get_mut(&2).unwrap().players.insert(994520424, player);
+        assert!(tracker.update(start + Duration::minutes(2), &snapshot).is_empty());
+
+        // Loading again on the next round keeps the team
+        let player = snapshot.team_info.get_mut(&2).unwrap().players.remove(&994520424).unwrap();
+        snapshot.team_info.get_mut(&0).unwrap().players.insert(994520424, player);
+        assert!(tracker.update(start + Duration::minutes(3), &snapshot).is_empty());
+
+        let player = snapshot.team_info.get_mut(&0).unwrap().players.remove(&994520424).unwrap();
+        snapshot.team_info.get_mut(&2).unwrap().players.insert(994520424, player);
+        assert!(tracker.update(start + Duration::minutes(4), &snapshot).is_empty());
+        assert_eq!(2, tracker.sessions[&994520424].session.team);
+        assert_eq!(0, tracker.sessions[&994520424].session.team_switches);
+    }
+}
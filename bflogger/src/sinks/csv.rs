@@ -0,0 +1,181 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use csv::{ReaderBuilder, Writer};
+use serde_json::Value;
+
+use super::{Sink, SinkError};
+use crate::records::RecordBatch;
+
+/// Appends the records to one CSV file per measurement, `<directory>/<measurement>.csv`.
+///
+/// The columns are the fields of the record in declaration order, followed by a `tags` column
+/// with the server tags as `key=value` pairs, so servers with different tags share the files.
+/// A file whose header doesn't match the columns of this version is moved aside to
+/// `<measurement>.<time>.csv` and started over.
+pub struct CsvSink {
+    directory: PathBuf,
+    files: Mutex<HashMap<&'static str, CsvFile>>,
+}
+
+struct CsvFile {
+    writer: Writer<File>,
+    columns: Vec<String>,
+}
+
+impl CsvSink {
+    pub fn open(directory: &Path) -> Result<Self, SinkError> {
+        fs::create_dir_all(directory)?;
+
+        Ok(Self {
+            directory: directory.to_path_buf(),
+            files: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn open_file(&self, measurement: &str, columns: Vec<String>) -> Result<CsvFile, SinkError> {
+        let path = self.directory.join(format!("{}.csv", measurement));
+        if let Some(header) = read_header(&path)? {
+            if header != columns {
+                let moved = self
+                    .directory
+                    .join(format!("{}.{}.csv", measurement, Utc::now().format("%Y%m%dT%H%M%S")));
+                eprintln!(
+                    "The columns of {} changed, moving it to {} and starting a new file",
+                    path.display(),
+                    moved.display()
+                );
+                fs::rename(&path, &moved)?;
+            }
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let is_new = file.metadata()?.len() == 0;
+
+        let mut writer = Writer::from_writer(file);
+        if is_new {
+            writer.write_record(&columns)?;
+        }
+        Ok(CsvFile { writer, columns })
+    }
+}
+
+/// The first line of an existing file, `None` if there is none or it's empty.
+fn read_header(path: &Path) -> Result<Option<Vec<String>>, SinkError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let mut reader = ReaderBuilder::new().has_headers(false).flexible(true).from_reader(file);
+    match reader.records().next() {
+        Some(record) => Ok(Some(record?.iter().map(str::to_string).collect())),
+        None => Ok(None),
+    }
+}
+
+#[async_trait]
+impl Sink for CsvSink {
+    async fn write(&self, batch: &RecordBatch) -> Result<(), SinkError> {
+        let tags = batch.tags
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let mut files = self.files.lock().expect("CSV sink lock poisoned");
+        for record in &batch.records {
+            let mut row = record.to_json()?;
+            row.insert("server_name".to_string(), batch.server_name.clone().into());
+            row.insert("tags".to_string(), Value::String(tags.to_string()));
+
+            let measurement = record.measurement();
+            if !files.contains_key(measurement) {
+                // The file name already says what the measurement is
+                let columns = row.keys().filter(|column| *column != "measurement").cloned().collect();
+                let file = self.open_file(measurement, columns)?;
+                files.insert(measurement, file);
+            }
+            let file = files.get_mut(measurement).expect("opened above");
+
+            let values: Vec<String> = file.columns
+                .iter()
+                .map(|column| match row.get(column) {
+                    None | Some(Value::Null) => String::new(),
+                    Some(Value::String(value)) => value.to_string(),
+                    Some(value) => value.to_string(),
+                })
+                .collect();
+            file.writer.write_record(&values)?;
+        }
+
+        for file in files.values_mut() {
+            file.writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::records::{team_readings, Record};
+
+    #[tokio::test]
+    async fn appends_rows_per_measurement() {
+        let data = crate::fixture("snapshot_conquest");
+        let directory = std::env::temp_dir().join(format!("bflogger-csv-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+
+        let mut tags = BTreeMap::new();
+        tags.insert("server_label".to_string(), "BattleFox #1".to_string());
+        let batch = RecordBatch {
+            tags,
+            server_name: Some("=BFX= BattleFox #1".to_string()),
+            records: team_readings(Utc::now(), "4d0151b3-81ff-4268-b4e8-5e60d5bc8765", &data.snapshot)
+                .into_iter()
+                .map(Record::Team)
+                .collect(),
+        };
+
+        let sink = CsvSink::open(&directory).unwrap();
+        sink.write(&batch).await.unwrap();
+        sink.write(&batch).await.unwrap();
+
+        let contents = fs::read_to_string(directory.join("team.csv")).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        // One header and two teams per write
+        assert_eq!(5, lines.len());
+        assert!(lines[0].starts_with("time,server_guid,game_mode,team,tickets,tickets_max,"), "{}", lines[0]);
+        assert!(lines[0].ends_with(",server_name,tags"));
+        assert!(lines[1].contains(",ConquestLarge0,"), "{}", lines[1]);
+        assert!(lines[1].ends_with(",=BFX= BattleFox #1,server_label=BattleFox #1"), "{}", lines[1]);
+
+        // A file of an older version with other columns is moved aside
+        fs::write(directory.join("team.csv"), "time,server_guid,team\n1,guid,1\n").unwrap();
+        let sink = CsvSink::open(&directory).unwrap();
+        sink.write(&batch).await.unwrap();
+        let contents = fs::read_to_string(directory.join("team.csv")).unwrap();
+        assert_eq!(3, contents.lines().count());
+        assert_eq!(lines[0], contents.lines().next().unwrap());
+        let moved: Vec<_> = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("team.") && name != "team.csv")
+            .collect();
+        assert_eq!(1, moved.len(), "{:?}", moved);
+        assert_eq!("time,server_guid,team\n1,guid,1\n", fs::read_to_string(directory.join(&moved[0])).unwrap());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
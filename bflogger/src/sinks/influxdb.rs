@@ -0,0 +1,237 @@
+use std::{io::Write, time::Duration};
+
+use async_trait::async_trait;
+use flate2::{write::GzEncoder, Compression};
+use influxdb::Query;
+use reqwest::{
+    header::{AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE},
+    Client, RequestBuilder, Url,
+};
+
+use super::{Sink, SinkError};
+use crate::config::InfluxDbConfig;
+use crate::records::RecordBatch;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Writes the records as line protocol to InfluxDB, one measurement per record type.
+///
+/// Talks to the 2.x `/api/v2/write` endpoint when a token is configured and to the 1.x `/write`
+/// endpoint otherwise. Batches bigger than `batch_size` points are split into several requests.
+pub struct InfluxDbSink {
+    client: Client,
+    write_url: Url,
+    auth: Auth,
+    gzip: bool,
+    batch_size: usize,
+}
+
+enum Auth {
+    None,
+    Basic { username: String, password: Option<String> },
+    Token(String),
+}
+
+impl InfluxDbSink {
+    pub fn new(config: &InfluxDbConfig) -> Result<Self, SinkError> {
+        let base = config.url.trim_end_matches('/');
+        let (write_url, auth) = match &config.token {
+            Some(token) => (
+                Url::parse_with_params(&format!("{}/api/v2/write", base), &[
+                    ("org", config.org.as_deref().unwrap_or("")),
+                    ("bucket", config.bucket.as_deref().unwrap_or("")),
+                    ("precision", "ns"),
+                ]),
+                Auth::Token(token.to_string()),
+            ),
+            None => (
+                Url::parse_with_params(&format!("{}/write", base), &[
+                    ("db", config.database.as_deref().unwrap_or("")),
+                    ("precision", "ns"),
+                ]),
+                match &config.username {
+                    Some(username) => Auth::Basic {
+                        username: username.to_string(),
+                        password: config.password.clone(),
+                    },
+                    None => Auth::None,
+                },
+            ),
+        };
+        let write_url = write_url.map_err(|err| SinkError::Config(format!("invalid url {:?}: {}", config.url, err)))?;
+
+        Ok(Self {
+            client: Client::builder().timeout(TIMEOUT).build()?,
+            write_url,
+            auth,
+            gzip: config.gzip,
+            batch_size: config.batch_size.max(1),
+        })
+    }
+
+    fn request(&self, lines: &[String]) -> Result<RequestBuilder, SinkError> {
+        let body = lines.join("\n");
+        let mut request = self.client
+            .post(self.write_url.clone())
+            .header(CONTENT_TYPE, "text/plain; charset=utf-8");
+
+        request = match &self.auth {
+            Auth::None => request,
+            Auth::Basic { username, password } => request.basic_auth(username, password.as_ref()),
+            Auth::Token(token) => request.header(AUTHORIZATION, format!("Token {}", token)),
+        };
+
+        Ok(if self.gzip {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body.as_bytes())?;
+            request.header(CONTENT_ENCODING, "gzip").body(encoder.finish()?)
+        } else {
+            request.body(body)
+        })
+    }
+}
+
+#[async_trait]
+impl Sink for InfluxDbSink {
+    async fn write(&self, batch: &RecordBatch) -> Result<(), SinkError> {
+        let lines = batch.records
+            .iter()
+            .cloned()
+            .map(|record| {
+                let mut query = record.into_query().add_field("server_name", batch.server_name.as_deref());
+                // InfluxDB rejects the whole write for an empty tag value
+                for (key, value) in batch.tags.iter().filter(|(_, value)| !value.is_empty()) {
+                    query = query.add_tag(key.as_str(), value.as_str());
+                }
+                query.build().map(|query| query.get())
+            })
+            .collect::<Result<Vec<String>, _>>()?;
+
+        for chunk in lines.chunks(self.batch_size) {
+            let response = self.request(chunk)?.send().await?;
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await.unwrap_or_default();
+                return Err(SinkError::Http { status, body });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, io::Read};
+
+    use chrono::Utc;
+    use flate2::read::GzDecoder;
+    use wiremock::{
+        matchers::{basic_auth, header, method, path, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+    use crate::records::{player_readings, team_readings, Record};
+
+    fn team_batch() -> RecordBatch {
+        let data = crate::fixture("snapshot_conquest");
+        let mut tags = BTreeMap::new();
+        tags.insert("server_label".to_string(), "BattleFox #1".to_string());
+        RecordBatch {
+            tags,
+            server_name: Some("=BFX= BattleFox #1".to_string()),
+            records: team_readings(Utc::now(), "4d0151b3-81ff-4268-b4e8-5e60d5bc8765", &data.snapshot)
+                .into_iter()
+                .map(Record::Team)
+                .collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn write_v2_gzip_batches() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v2/write"))
+            .and(query_param("org", "BattleFox"))
+            .and(query_param("bucket", "bflogger"))
+            .and(header("authorization", "Token secret"))
+            .and(header("content-encoding", "gzip"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let mut config = InfluxDbConfig::new(&server.uri(), "unused");
+        config.org = Some("BattleFox".to_string());
+        config.bucket = Some("bflogger".to_string());
+        config.token = Some("secret".to_string());
+        config.batch_size = 1;
+        InfluxDbSink::new(&config).unwrap().write(&team_batch()).await.unwrap();
+
+        // One point per request with the batch size of one
+        let mut bodies = Vec::new();
+        for request in server.received_requests().await.unwrap() {
+            let mut body = String::new();
+            GzDecoder::new(request.body.as_slice()).read_to_string(&mut body).unwrap();
+            assert!(body.starts_with("team,"), "{}", body);
+            assert!(body.contains("server_label=BattleFox\\ #1"), "{}", body);
+            assert!(body.contains("server_name=\"=BFX= BattleFox #1\""), "{}", body);
+            bodies.push(body);
+        }
+        assert!(bodies.iter().any(|body| body.contains(" tickets=412i,")), "{:?}", bodies);
+    }
+
+    #[tokio::test]
+    async fn write_without_empty_tags() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/write"))
+            .respond_with(ResponseTemplate::new(204))
+            .mount(&server)
+            .await;
+
+        let data = crate::fixture("snapshot_rush");
+        let mut tags = BTreeMap::new();
+        tags.insert("region".to_string(), String::new());
+        let batch = RecordBatch {
+            tags,
+            server_name: None,
+            records: player_readings(Utc::now(), "4d0151b3-81ff-4268-b4e8-5e60d5bc8765", &data.snapshot)
+                .into_iter()
+                .map(Record::Player)
+                .collect(),
+        };
+        let mut config = InfluxDbConfig::new(&server.uri(), "bflogger");
+        config.gzip = false;
+        InfluxDbSink::new(&config).unwrap().write(&batch).await.unwrap();
+
+        // One of the players has no clan
+        let requests = server.received_requests().await.unwrap();
+        let body = String::from_utf8_lossy(&requests[0].body);
+        assert_eq!(batch.records.len(), body.lines().count());
+        assert!(body.lines().any(|line| !line.contains("clan_tag")), "{}", body);
+        assert!(!body.contains("=,") && !body.contains("= ") && !body.contains("region"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn write_v1_basic_auth_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/write"))
+            .and(query_param("db", "bflogger"))
+            .and(basic_auth("bflogger", "secret"))
+            .respond_with(ResponseTemplate::new(401).set_body_string("{\"error\":\"authorization failed\"}"))
+            .mount(&server)
+            .await;
+
+        let mut config = InfluxDbConfig::new(&server.uri(), "bflogger");
+        config.username = Some("bflogger".to_string());
+        config.password = Some("secret".to_string());
+        config.gzip = false;
+        let err = InfluxDbSink::new(&config).unwrap().write(&team_batch()).await.unwrap_err();
+
+        assert!(matches!(err, SinkError::Http { status, .. } if status == 401), "{}", err);
+        let requests = server.received_requests().await.unwrap();
+        assert!(String::from_utf8_lossy(&requests[0].body).contains("tickets=356"));
+    }
+}
@@ -0,0 +1,44 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use async_trait::async_trait;
+
+use super::{Sink, SinkError};
+use crate::records::RecordBatch;
+
+/// Appends the records to a file as newline-delimited JSON, one object per record.
+pub struct JsonSink {
+    file: Mutex<BufWriter<File>>,
+}
+
+impl JsonSink {
+    pub fn open(path: &Path) -> Result<Self, SinkError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self {
+            file: Mutex::new(BufWriter::new(file)),
+        })
+    }
+}
+
+#[async_trait]
+impl Sink for JsonSink {
+    async fn write(&self, batch: &RecordBatch) -> Result<(), SinkError> {
+        let lines = batch.to_json()?;
+
+        let mut file = self.file.lock().expect("JSON sink lock poisoned");
+        for line in lines {
+            serde_json::to_writer(&mut *file, &line)?;
+            file.write_all(b"\n")?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+}
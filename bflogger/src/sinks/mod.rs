@@ -0,0 +1,146 @@
+//! Destinations for the records of the server polls.
+//!
+//! Every sink in the config is created once and shared by the servers that write to it.
+
+mod csv;
+mod influxdb;
+mod json;
+mod postgres;
+mod queue;
+mod sqlite;
+mod stdout;
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use async_trait::async_trait;
+use thiserror::Error;
+
+use crate::config::SinkConfig;
+use crate::records::RecordBatch;
+
+pub use self::csv::CsvSink;
+pub use self::influxdb::InfluxDbSink;
+pub use self::json::JsonSink;
+pub use self::postgres::PostgresSink;
+pub use self::queue::SinkQueue;
+pub use self::sqlite::{MapPopulation, RotationRow, RoundRow, SqliteSink};
+pub use self::stdout::StdoutSink;
+
+#[derive(Debug, Error)]
+pub enum SinkError {
+    #[error("invalid config: {0}")]
+    Config(String),
+    #[error("InfluxDB: {0}")]
+    Influxdb(#[from] ::influxdb::Error),
+    #[error("HTTP request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("HTTP status {status}: {body}")]
+    Http { status: reqwest::StatusCode, body: String },
+    #[error("I/O: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("CSV: {0}")]
+    Csv(#[from] ::csv::Error),
+    #[error("JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("PostgreSQL: {0}")]
+    Postgres(#[from] tokio_postgres::Error),
+    #[error("SQLite: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+impl SinkError {
+    /// Whether writing the same batch again may succeed. The other errors come from the batch
+    /// itself, like a point the database rejects, and would fail every retry.
+    pub fn is_retryable(&self) -> bool {
+        use reqwest::StatusCode;
+        use rusqlite::ErrorCode;
+
+        match self {
+            SinkError::Config(_) => false,
+            SinkError::Influxdb(err) => !matches!(err, ::influxdb::Error::InvalidQueryError { .. }),
+            SinkError::Request(err) => !err.is_builder(),
+            // The credentials and the database are settings of the sink, not of the batch
+            SinkError::Http { status, .. } => {
+                !status.is_client_error()
+                    || matches!(
+                        *status,
+                        StatusCode::UNAUTHORIZED
+                            | StatusCode::FORBIDDEN
+                            | StatusCode::NOT_FOUND
+                            | StatusCode::REQUEST_TIMEOUT
+                            | StatusCode::TOO_MANY_REQUESTS
+                    )
+            }
+            SinkError::Io(_) => true,
+            SinkError::Csv(err) => matches!(err.kind(), ::csv::ErrorKind::Io(_)),
+            SinkError::Json(err) => err.is_io(),
+            // Bad data, integrity constraints and SQL errors fail again, the connection and
+            // resource errors don't
+            SinkError::Postgres(err) => err
+                .as_db_error()
+                .is_none_or(|err| !["22", "23", "42"].contains(&&err.code().code()[..2])),
+            SinkError::Sqlite(err) => matches!(
+                err.sqlite_error_code(),
+                Some(
+                    ErrorCode::DatabaseBusy
+                        | ErrorCode::DatabaseLocked
+                        | ErrorCode::DiskFull
+                        | ErrorCode::SystemIoFailure
+                        | ErrorCode::CannotOpen
+                        | ErrorCode::OutOfMemory
+                )
+            ),
+        }
+    }
+}
+
+#[async_trait]
+pub trait Sink: Send + Sync {
+    /// Writes every record of the batch. Sinks that buffer must not hold on to records after
+    /// this returns.
+    async fn write(&self, batch: &RecordBatch) -> Result<(), SinkError>;
+}
+
+/// Creates the sinks defined in the config, by name.
+pub fn build(sinks: &BTreeMap<String, SinkConfig>) -> Result<BTreeMap<String, Arc<dyn Sink>>, SinkError> {
+    let mut built: BTreeMap<String, Arc<dyn Sink>> = BTreeMap::new();
+    for (name, config) in sinks {
+        let sink: Arc<dyn Sink> = match config {
+            SinkConfig::Influxdb(influxdb) => Arc::new(InfluxDbSink::new(influxdb)?),
+            SinkConfig::Json { path } => Arc::new(JsonSink::open(path)?),
+            SinkConfig::Csv { directory } => Arc::new(CsvSink::open(directory)?),
+            SinkConfig::Stdout => Arc::new(StdoutSink),
+            SinkConfig::Postgres { url, timescaledb } => Arc::new(PostgresSink::new(url, *timescaledb)),
+            SinkConfig::Sqlite { path, retention_days } => Arc::new(SqliteSink::open(path, *retention_days)?),
+        };
+        built.insert(name.to_string(), sink);
+    }
+    Ok(built)
+}
+
+/// Hands every batch to the queues of several sinks at once, so a failing sink neither holds up
+/// the poll nor the other sinks.
+#[derive(Clone)]
+pub struct Fanout {
+    queues: Vec<Arc<SinkQueue>>,
+}
+
+impl Fanout {
+    /// Picks the named sinks, unknown names are skipped since the config validation rejects them.
+    pub fn new(queues: &BTreeMap<String, Arc<SinkQueue>>, names: &[String]) -> Self {
+        Self {
+            queues: names.iter().filter_map(|name| queues.get(name).cloned()).collect(),
+        }
+    }
+
+    pub fn write(&self, batch: RecordBatch) {
+        if batch.is_empty() {
+            return;
+        }
+
+        let batch = Arc::new(batch);
+        for queue in &self.queues {
+            queue.push(batch.clone());
+        }
+    }
+}
@@ -0,0 +1,472 @@
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use tokio::sync::Mutex;
+use tokio_postgres::{Client, NoTls, Statement};
+
+use super::{Sink, SinkError};
+use crate::records::{Record, RecordBatch};
+
+/// Schema changes, applied in order. Never edit a migration that has been released, add a new one.
+const MIGRATIONS: &[&str] = &[
+    // 1: one table per measurement, the rounds and sessions get a row once they're over
+    "
+    CREATE TABLE snapshots (
+        time TIMESTAMPTZ NOT NULL,
+        server_guid TEXT NOT NULL,
+        game_id BIGINT NOT NULL,
+        game_mode TEXT NOT NULL,
+        current_map TEXT NOT NULL,
+        map_variant SMALLINT NOT NULL,
+        max_players SMALLINT NOT NULL,
+        waiting_players SMALLINT NOT NULL,
+        players INTEGER NOT NULL,
+        round_time BIGINT NOT NULL,
+        round_running BOOLEAN NOT NULL,
+        tags JSONB NOT NULL DEFAULT '{}'
+    );
+    CREATE INDEX snapshots_server_guid_time ON snapshots (server_guid, time DESC);
+
+    CREATE TABLE teams (
+        time TIMESTAMPTZ NOT NULL,
+        server_guid TEXT NOT NULL,
+        game_mode TEXT NOT NULL,
+        team SMALLINT NOT NULL,
+        tickets BIGINT,
+        tickets_max BIGINT,
+        bases SMALLINT,
+        bases_max SMALLINT,
+        kills BIGINT,
+        kills_max BIGINT,
+        destroyed_crates SMALLINT,
+        carrier_health SMALLINT,
+        score BIGINT,
+        score_max BIGINT,
+        flags SMALLINT,
+        flags_max SMALLINT,
+        rounds SMALLINT,
+        rounds_max SMALLINT,
+        tags JSONB NOT NULL DEFAULT '{}'
+    );
+    CREATE INDEX teams_server_guid_time ON teams (server_guid, time DESC);
+
+    CREATE TABLE players (
+        time TIMESTAMPTZ NOT NULL,
+        server_guid TEXT NOT NULL,
+        persona_id BIGINT NOT NULL,
+        name TEXT NOT NULL,
+        clan_tag TEXT NOT NULL,
+        team SMALLINT NOT NULL,
+        game_id BIGINT NOT NULL,
+        score BIGINT NOT NULL,
+        kills BIGINT NOT NULL,
+        deaths BIGINT NOT NULL,
+        kd DOUBLE PRECISION NOT NULL,
+        squad SMALLINT NOT NULL,
+        rank SMALLINT NOT NULL,
+        role SMALLINT NOT NULL,
+        tags JSONB NOT NULL DEFAULT '{}'
+    );
+    CREATE INDEX players_persona_id_time ON players (persona_id, time DESC);
+
+    CREATE TABLE round_events (
+        time TIMESTAMPTZ NOT NULL,
+        server_guid TEXT NOT NULL,
+        event TEXT NOT NULL,
+        game_id BIGINT NOT NULL,
+        current_map TEXT NOT NULL,
+        previous_map TEXT,
+        winner SMALLINT,
+        tags JSONB NOT NULL DEFAULT '{}'
+    );
+
+    CREATE TABLE rounds (
+        ended_at TIMESTAMPTZ NOT NULL,
+        started_at TIMESTAMPTZ NOT NULL,
+        server_guid TEXT NOT NULL,
+        game_id BIGINT NOT NULL,
+        game_mode TEXT NOT NULL,
+        current_map TEXT NOT NULL,
+        duration BIGINT NOT NULL,
+        winner SMALLINT,
+        team1_score BIGINT,
+        team2_score BIGINT,
+        scores TEXT NOT NULL,
+        peak_players INTEGER NOT NULL,
+        top_scorers TEXT NOT NULL,
+        tags JSONB NOT NULL DEFAULT '{}'
+    );
+    CREATE INDEX rounds_server_guid_ended_at ON rounds (server_guid, ended_at DESC);
+
+    CREATE TABLE session_events (
+        time TIMESTAMPTZ NOT NULL,
+        server_guid TEXT NOT NULL,
+        event TEXT NOT NULL,
+        persona_id BIGINT NOT NULL,
+        name TEXT NOT NULL,
+        team SMALLINT NOT NULL,
+        previous_team SMALLINT,
+        tags JSONB NOT NULL DEFAULT '{}'
+    );
+
+    CREATE TABLE sessions (
+        left_at TIMESTAMPTZ NOT NULL,
+        joined_at TIMESTAMPTZ NOT NULL,
+        server_guid TEXT NOT NULL,
+        persona_id BIGINT NOT NULL,
+        name TEXT NOT NULL,
+        clan_tag TEXT NOT NULL,
+        team SMALLINT NOT NULL,
+        duration BIGINT NOT NULL,
+        team_switches BIGINT NOT NULL,
+        score BIGINT NOT NULL,
+        kills BIGINT NOT NULL,
+        deaths BIGINT NOT NULL,
+        tags JSONB NOT NULL DEFAULT '{}'
+    );
+    CREATE INDEX sessions_persona_id_left_at ON sessions (persona_id, left_at DESC);
+    ",
+    // 2: the outcome of every poll
+    "
+    CREATE TABLE poll_status (
+        time TIMESTAMPTZ NOT NULL,
+        server_guid TEXT NOT NULL,
+        status TEXT NOT NULL,
+        latency_ms BIGINT NOT NULL,
+        consecutive_failures BIGINT NOT NULL,
+        error TEXT,
+        tags JSONB NOT NULL DEFAULT '{}'
+    );
+    CREATE INDEX poll_status_server_guid_time ON poll_status (server_guid, time DESC);
+    ",
+    // 3: the rotation of the servers, and how far the rounds got
+    "
+    CREATE TABLE rotations (
+        time TIMESTAMPTZ NOT NULL,
+        server_guid TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        current_map TEXT NOT NULL,
+        game_mode TEXT NOT NULL,
+        tags JSONB NOT NULL DEFAULT '{}'
+    );
+    CREATE INDEX rotations_server_guid_time ON rotations (server_guid, time DESC);
+
+    ALTER TABLE rounds ADD COLUMN progress DOUBLE PRECISION;
+    ",
+    // 4: the name of the server on Battlelog
+    "
+    ALTER TABLE snapshots ADD COLUMN server_name TEXT;
+    ",
+    // 5: the server name on the other tables too
+    "
+    ALTER TABLE teams ADD COLUMN server_name TEXT;
+    ALTER TABLE players ADD COLUMN server_name TEXT;
+    ALTER TABLE round_events ADD COLUMN server_name TEXT;
+    ALTER TABLE rounds ADD COLUMN server_name TEXT;
+    ALTER TABLE session_events ADD COLUMN server_name TEXT;
+    ALTER TABLE sessions ADD COLUMN server_name TEXT;
+    ALTER TABLE poll_status ADD COLUMN server_name TEXT;
+    ALTER TABLE rotations ADD COLUMN server_name TEXT;
+    ",
+];
+
+/// The tables that grow with every poll, turned into hypertables on TimescaleDB.
+const HYPERTABLES: &[&str] = &["snapshots", "teams", "players", "poll_status"];
+
+/// Writes the records into PostgreSQL tables, one transaction per batch.
+///
+/// The tables are created and migrated on the first write, which also reconnects after the
+/// connection was lost. With `timescaledb` the busy tables are turned into hypertables.
+pub struct PostgresSink {
+    url: String,
+    timescaledb: bool,
+    connection: Mutex<Option<Connection>>,
+}
+
+struct Connection {
+    client: Client,
+    snapshot: Statement,
+    team: Statement,
+    player: Statement,
+    round_event: Statement,
+    round: Statement,
+    session_event: Statement,
+    session: Statement,
+    poll_status: Statement,
+    rotation: Statement,
+}
+
+impl PostgresSink {
+    pub fn new(url: &str, timescaledb: bool) -> Self {
+        Self {
+            url: url.to_string(),
+            timescaledb,
+            connection: Mutex::new(None),
+        }
+    }
+
+    async fn connect(&self) -> Result<Connection, SinkError> {
+        let (mut client, connection) = tokio_postgres::connect(&self.url, NoTls).await?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                eprintln!("PostgreSQL connection error: {}", err);
+            }
+        });
+
+        migrate(&mut client).await?;
+        if self.timescaledb {
+            for table in HYPERTABLES {
+                client
+                    .execute(
+                        "SELECT create_hypertable($1::text::regclass, 'time', if_not_exists => TRUE, migrate_data => TRUE)",
+                        &[table],
+                    )
+                    .await?;
+            }
+        }
+
+        Ok(Connection {
+            snapshot: client.prepare(
+                "INSERT INTO snapshots (time, server_guid, game_id, game_mode, current_map, map_variant, max_players,
+                    waiting_players, players, round_time, round_running, server_name, tags)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)",
+            ).await?,
+            team: client.prepare(
+                "INSERT INTO teams (time, server_guid, game_mode, team, tickets, tickets_max, bases, bases_max, kills,
+                    kills_max, destroyed_crates, carrier_health, score, score_max, flags, flags_max, rounds, rounds_max,
+                    server_name, tags)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20)",
+            ).await?,
+            player: client.prepare(
+                "INSERT INTO players (time, server_guid, persona_id, name, clan_tag, team, game_id, score, kills, deaths,
+                    kd, squad, rank, role, server_name, tags)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)",
+            ).await?,
+            round_event: client.prepare(
+                "INSERT INTO round_events (time, server_guid, event, game_id, current_map, previous_map, winner,
+                    server_name, tags)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            ).await?,
+            round: client.prepare(
+                "INSERT INTO rounds (ended_at, started_at, server_guid, game_id, game_mode, current_map, duration, winner,
+                    team1_score, team2_score, scores, peak_players, top_scorers, progress, server_name, tags)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)",
+            ).await?,
+            session_event: client.prepare(
+                "INSERT INTO session_events (time, server_guid, event, persona_id, name, team, previous_team,
+                    server_name, tags)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            ).await?,
+            session: client.prepare(
+                "INSERT INTO sessions (left_at, joined_at, server_guid, persona_id, name, clan_tag, team, duration,
+                    team_switches, score, kills, deaths, server_name, tags)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)",
+            ).await?,
+            poll_status: client.prepare(
+                "INSERT INTO poll_status (time, server_guid, status, latency_ms, consecutive_failures, error,
+                    server_name, tags)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            ).await?,
+            rotation: client.prepare(
+                "INSERT INTO rotations (time, server_guid, position, current_map, game_mode, server_name, tags)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            ).await?,
+            client,
+        })
+    }
+}
+
+impl Connection {
+    async fn insert(&mut self, batch: &RecordBatch) -> Result<(), SinkError> {
+        let tags = serde_json::to_value(&batch.tags)?;
+        let server_name = batch.server_name.as_deref();
+        let tx = self.client.transaction().await?;
+
+        for record in &batch.records {
+            match record {
+                Record::Snapshot(r) => {
+                    tx.execute(&self.snapshot, &[
+                        &r.time, &r.server_guid, &(r.game_id as i64), &r.game_mode, &r.current_map,
+                        &i16::from(r.map_variant), &i16::from(r.max_players), &i16::from(r.waiting_players),
+                        &i32::from(r.players), &i64::from(r.round_time), &r.round_running, &server_name, &tags,
+                    ]).await?;
+                }
+                Record::Team(r) => {
+                    tx.execute(&self.team, &[
+                        &r.time, &r.server_guid, &r.game_mode, &i16::from(r.team),
+                        &r.tickets.map(i64::from), &r.tickets_max.map(i64::from),
+                        &r.bases.map(i16::from), &r.bases_max.map(i16::from),
+                        &r.kills.map(i64::from), &r.kills_max.map(i64::from),
+                        &r.destroyed_crates.map(i16::from), &r.carrier_health.map(i16::from),
+                        &r.score.map(i64::from), &r.score_max.map(i64::from),
+                        &r.flags.map(i16::from), &r.flags_max.map(i16::from),
+                        &r.rounds.map(i16::from), &r.rounds_max.map(i16::from), &server_name, &tags,
+                    ]).await?;
+                }
+                Record::Player(r) => {
+                    tx.execute(&self.player, &[
+                        &r.time, &r.server_guid, &(r.persona_id as i64), &r.name, &r.clan_tag.as_deref().unwrap_or(""), &i16::from(r.team),
+                        &(r.game_id as i64), &i64::from(r.score), &i64::from(r.kills), &i64::from(r.deaths), &r.kd,
+                        &i16::from(r.squad), &r.rank, &i16::from(r.role), &server_name, &tags,
+                    ]).await?;
+                }
+                Record::RoundEvent(r) => {
+                    tx.execute(&self.round_event, &[
+                        &r.time, &r.server_guid, &r.event, &(r.game_id as i64), &r.current_map, &r.previous_map,
+                        &r.winner.map(i16::from), &server_name, &tags,
+                    ]).await?;
+                }
+                Record::RoundSummary(r) => {
+                    tx.execute(&self.round, &[
+                        &r.time, &timestamp(r.started_at), &r.server_guid, &(r.game_id as i64), &r.game_mode,
+                        &r.current_map, &i64::from(r.duration), &r.winner.map(i16::from),
+                        &r.team1_score.map(i64::from), &r.team2_score.map(i64::from), &r.scores,
+                        &i32::from(r.peak_players), &r.top_scorers, &r.progress, &server_name, &tags,
+                    ]).await?;
+                }
+                Record::SessionEvent(r) => {
+                    tx.execute(&self.session_event, &[
+                        &r.time, &r.server_guid, &r.event, &(r.persona_id as i64), &r.name, &i16::from(r.team),
+                        &r.previous_team.map(i16::from), &server_name, &tags,
+                    ]).await?;
+
+                    // Only the leave events carry the totals of the session
+                    if let Some(joined_at) = r.joined_at {
+                        let left_at = r.left_at.map(timestamp).unwrap_or(r.time);
+                        tx.execute(&self.session, &[
+                            &left_at, &timestamp(joined_at), &r.server_guid, &(r.persona_id as i64), &r.name,
+                            &r.clan_tag.as_deref().unwrap_or(""), &i16::from(r.team), &r.duration.unwrap_or(0),
+                            &i64::from(r.team_switches.unwrap_or(0)), &i64::from(r.score.unwrap_or(0)),
+                            &i64::from(r.kills.unwrap_or(0)), &i64::from(r.deaths.unwrap_or(0)), &server_name, &tags,
+                        ]).await?;
+                    }
+                }
+                Record::PollStatus(r) => {
+                    tx.execute(&self.poll_status, &[
+                        &r.time, &r.server_guid, &r.status, &(r.latency_ms as i64), &i64::from(r.consecutive_failures),
+                        &r.error, &server_name, &tags,
+                    ]).await?;
+                }
+                Record::Rotation(r) => {
+                    tx.execute(&self.rotation, &[
+                        &r.time, &r.server_guid, &(r.position as i32), &r.current_map, &r.game_mode, &server_name, &tags,
+                    ]).await?;
+                }
+            }
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for PostgresSink {
+    async fn write(&self, batch: &RecordBatch) -> Result<(), SinkError> {
+        let mut connection = self.connection.lock().await;
+        if connection.as_ref().is_none_or(|connection| connection.client.is_closed()) {
+            *connection = Some(self.connect().await?);
+        }
+
+        connection.as_mut().expect("connected above").insert(batch).await
+    }
+}
+
+/// Applies the migrations that the database hasn't seen yet, each in its own transaction.
+async fn migrate(client: &mut Client) -> Result<(), SinkError> {
+    client
+        .batch_execute(
+            "CREATE TABLE IF NOT EXISTS bflogger_migrations (
+                version INTEGER PRIMARY KEY,
+                applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            )",
+        )
+        .await?;
+
+    let row = client.query_one("SELECT COALESCE(MAX(version), 0) FROM bflogger_migrations", &[]).await?;
+    let current: i32 = row.get(0);
+
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        let version = version as i32 + 1;
+        eprintln!("Applying PostgreSQL migration {}", version);
+
+        let tx = client.transaction().await?;
+        tx.batch_execute(migration).await?;
+        tx.execute("INSERT INTO bflogger_migrations (version) VALUES ($1)", &[&version]).await?;
+        tx.commit().await?;
+    }
+    Ok(())
+}
+
+fn timestamp(secs: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(secs, 0).single().unwrap_or_else(Utc::now)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+
+    use super::*;
+    use crate::records::{team_readings, SessionEventReading, SnapshotReading};
+    use crate::session::{PlayerSession, SessionEvent};
+
+    /// Runs against the database in `POSTGRES_TEST_URL`, for example the one of
+    /// `docker-compose.postgres.yml`, run with `cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore = "needs the PostgreSQL database in POSTGRES_TEST_URL"]
+    async fn write_batch() {
+        let url = std::env::var("POSTGRES_TEST_URL").expect("POSTGRES_TEST_URL must point to a PostgreSQL database");
+        let data = crate::fixture("snapshot_conquest");
+        let server_guid = format!("test-{}", Utc::now().timestamp_nanos_opt().unwrap());
+        let time = Utc::now();
+
+        let mut records = vec![Record::Snapshot(SnapshotReading::new(time, &server_guid, &data.snapshot))];
+        records.extend(team_readings(time, &server_guid, &data.snapshot).into_iter().map(Record::Team));
+        let session = PlayerSession {
+            persona_id: 806262072,
+            name: "xfileFIN".to_string(),
+            clan_tag: "BFOX".to_string(),
+            team: 1,
+            joined_at: time - chrono::Duration::hours(1),
+            left_at: time - chrono::Duration::seconds(30),
+            team_switches: 0,
+            score: 3120,
+            kills: 20,
+            deaths: 5,
+        };
+        records.push(Record::SessionEvent(SessionEventReading::new(time, &server_guid, &SessionEvent::Left(session))));
+        let batch = RecordBatch {
+            tags: BTreeMap::new(),
+            server_name: Some("=BFX= BattleFox #1".to_string()),
+            records,
+        };
+
+        let sink = PostgresSink::new(&url, false);
+        sink.write(&batch).await.unwrap();
+        // A second sink runs the migrations again, which must be a no-op
+        PostgresSink::new(&url, false).write(&batch).await.unwrap();
+
+        let connection = sink.connection.lock().await;
+        let client = &connection.as_ref().unwrap().client;
+        let snapshots: i64 = client
+            .query_one("SELECT COUNT(*) FROM snapshots WHERE server_guid = $1", &[&server_guid])
+            .await
+            .unwrap()
+            .get(0);
+        let tickets: i64 = client
+            .query_one("SELECT tickets FROM teams WHERE server_guid = $1 AND team = 1 LIMIT 1", &[&server_guid])
+            .await
+            .unwrap()
+            .get(0);
+        let (left_at, server_name): (DateTime<Utc>, String) = client
+            .query_one("SELECT left_at, server_name FROM sessions WHERE server_guid = $1 LIMIT 1", &[&server_guid])
+            .await
+            .map(|row| (row.get(0), row.get(1)))
+            .unwrap();
+        assert_eq!(2, snapshots);
+        assert_eq!(412, tickets);
+        // When the player was last seen, not when the leave was noticed
+        assert_eq!((time - chrono::Duration::seconds(30)).timestamp(), left_at.timestamp());
+        assert_eq!("=BFX= BattleFox #1", server_name);
+    }
+}
@@ -0,0 +1,736 @@
+//! Retries the batches a sink fails to write, so a database restart doesn't leave gaps.
+//!
+//! Every sink gets a queue and a task that writes the batches in order, backing off while the
+//! sink keeps failing. A batch the sink rejects for good, like a point the database refuses, is
+//! dropped instead so it doesn't hold up the ones behind it. The queue holds up to `max_points`
+//! records in memory. Beyond that the newest batches go to a spool file when a spool directory is
+//! configured, and the oldest ones are dropped otherwise. The spool file is read back once the
+//! memory queue is empty, and it survives restarts of the logger.
+//!
+//! The spool file is only appended to. How far it has been read back is kept next to it in a
+//! `.offset` file, so reading back a chunk never loads or rewrites the rest. The file I/O runs
+//! on the blocking threads, the appends in order through their own task.
+
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use tokio::{
+    sync::{mpsc, oneshot, Notify},
+    task::spawn_blocking,
+    time::{sleep, timeout_at, Instant},
+};
+
+use super::{Sink, SinkError};
+use crate::config::QueueConfig;
+use crate::metrics::Metrics;
+use crate::records::RecordBatch;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+struct State {
+    /// Oldest first, the front is the one being written
+    batches: VecDeque<Arc<RecordBatch>>,
+    /// Records of the batches in memory
+    points: usize,
+    /// Records in the spool file or on their way there, which are all newer than the ones in memory
+    spooled: usize,
+    /// Set when the flush gave up, the writer task stops and leaves the spool file alone
+    closed: bool,
+}
+
+/// What the spool task does with the file, in the order they're sent.
+enum SpoolOp {
+    Append(Arc<RecordBatch>),
+    /// Puts the batches in front of the unread part of the file, for the shutdown
+    Prepend(Vec<Arc<RecordBatch>>, oneshot::Sender<Result<(), SinkError>>),
+}
+
+pub struct SinkQueue {
+    name: String,
+    max_points: usize,
+    spool: Option<Arc<Mutex<Spool>>>,
+    /// Hands the spool file operations to the spool task, `None` without a spool directory
+    spool_ops: Option<mpsc::UnboundedSender<SpoolOp>>,
+    state: Mutex<State>,
+    /// Wakes the writer task when a batch is queued
+    notify: Notify,
+    /// Wakes the flush when everything is written
+    idle: Notify,
+    metrics: Arc<Metrics>,
+}
+
+impl SinkQueue {
+    /// Creates the queue of the sink and spawns the task that writes it.
+    pub fn start(name: &str, sink: Arc<dyn Sink>, config: &QueueConfig, metrics: Arc<Metrics>) -> Result<Arc<Self>, SinkError> {
+        // Whatever is left over from the last run is written first
+        let (spool, spooled) = match &config.spool_directory {
+            Some(directory) => {
+                fs::create_dir_all(directory)?;
+                let (spool, spooled) = Spool::open(directory.join(format!("{}.ndjson", name)))?;
+                (Some(Arc::new(Mutex::new(spool))), spooled)
+            }
+            None => (None, 0),
+        };
+        if spooled > 0 {
+            eprintln!("Sink {} has {} spooled points from an earlier run", name, spooled);
+        }
+
+        let (spool_ops, ops) = match spool {
+            Some(_) => {
+                let (sender, receiver) = mpsc::unbounded_channel();
+                (Some(sender), Some(receiver))
+            }
+            None => (None, None),
+        };
+        let queue = Arc::new(Self {
+            name: name.to_string(),
+            max_points: config.max_points,
+            spool,
+            spool_ops,
+            state: Mutex::new(State {
+                spooled,
+                ..State::default()
+            }),
+            notify: Notify::new(),
+            idle: Notify::new(),
+            metrics,
+        });
+        queue.report(&queue.lock());
+
+        tokio::spawn(queue.clone().run(sink));
+        if let Some(ops) = ops {
+            tokio::spawn(queue.clone().run_spool(ops));
+        }
+        Ok(queue)
+    }
+
+    /// Queues the batch for writing, this never waits for the sink.
+    pub fn push(&self, batch: Arc<RecordBatch>) {
+        let mut state = self.lock();
+        let points = batch.records.len();
+
+        // Once anything is spooled, newer batches go after it to keep the order
+        let fits = state.points + points <= self.max_points || state.batches.is_empty();
+        if state.spooled == 0 && fits {
+            state.points += points;
+            state.batches.push_back(batch);
+        } else if let Some(spool_ops) = &self.spool_ops {
+            state.spooled += points;
+            // The spool task lives as long as the queue
+            let _ = spool_ops.send(SpoolOp::Append(batch));
+        } else {
+            let mut dropped = 0;
+            // The batch being written stays, its write is already under way
+            while state.points + points > self.max_points && state.batches.len() > 1 {
+                if let Some(oldest) = state.batches.remove(1) {
+                    state.points -= oldest.records.len();
+                    dropped += oldest.records.len();
+                }
+            }
+            state.points += points;
+            state.batches.push_back(batch);
+            if dropped > 0 {
+                eprintln!("Queue of sink {} is full, dropped the {} oldest points", self.name, dropped);
+                self.metrics.add_dropped(&self.name, dropped);
+            }
+        }
+
+        self.report(&state);
+        drop(state);
+        self.notify.notify_one();
+    }
+
+    async fn run(self: Arc<Self>, sink: Arc<dyn Sink>) {
+        let mut backoff = INITIAL_BACKOFF;
+
+        loop {
+            let next = {
+                let state = self.lock();
+                if state.closed {
+                    return;
+                }
+                (state.batches.front().cloned(), state.spooled > 0)
+            };
+            let batch = match next {
+                (Some(batch), _) => batch,
+                (None, true) => {
+                    // An append that's still under way wakes the task once it's done
+                    if !self.unspool().await {
+                        self.notify.notified().await;
+                    }
+                    continue;
+                }
+                (None, false) => {
+                    self.notify.notified().await;
+                    continue;
+                }
+            };
+
+            match sink.write(&batch).await {
+                Ok(()) => {
+                    let mut state = self.lock();
+                    if state.batches.front().is_some_and(|front| Arc::ptr_eq(front, &batch)) {
+                        state.batches.pop_front();
+                        state.points -= batch.records.len();
+                    }
+                    if backoff > INITIAL_BACKOFF {
+                        eprintln!(
+                            "Sink {} is writable again, {} points still queued",
+                            self.name,
+                            state.points + state.spooled
+                        );
+                    }
+                    self.report(&state);
+                    if state.batches.is_empty() && state.spooled == 0 {
+                        self.idle.notify_waiters();
+                    }
+                    backoff = INITIAL_BACKOFF;
+                }
+                Err(err) if !err.is_retryable() => {
+                    let mut state = self.lock();
+                    if state.batches.front().is_some_and(|front| Arc::ptr_eq(front, &batch)) {
+                        state.batches.pop_front();
+                        state.points -= batch.records.len();
+                    }
+                    eprintln!(
+                        "Sink {} rejected {} points, dropping them: {}",
+                        self.name,
+                        batch.records.len(),
+                        err
+                    );
+                    self.metrics.add_dropped(&self.name, batch.records.len());
+                    self.report(&state);
+                    if state.batches.is_empty() && state.spooled == 0 {
+                        self.idle.notify_waiters();
+                    }
+                }
+                Err(err) => {
+                    let queued = {
+                        let state = self.lock();
+                        state.points + state.spooled
+                    };
+                    eprintln!(
+                        "Error writing to sink {}, retrying in {} s with {} points queued: {}",
+                        self.name,
+                        backoff.as_secs(),
+                        queued,
+                        err
+                    );
+                    sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    /// Does the spool file operations one after the other, so the batches keep their order.
+    async fn run_spool(self: Arc<Self>, mut ops: mpsc::UnboundedReceiver<SpoolOp>) {
+        let spool = match &self.spool {
+            Some(spool) => spool.clone(),
+            None => return,
+        };
+
+        while let Some(op) = ops.recv().await {
+            let spool = spool.clone();
+            match op {
+                SpoolOp::Append(batch) => {
+                    let points = batch.records.len();
+                    let result = spawn_blocking(move || lock_spool(&spool).append(&batch))
+                        .await
+                        .unwrap_or_else(|err| Err(io::Error::other(err).into()));
+                    if let Err(err) = result {
+                        eprintln!("Error spooling {} points for sink {}, dropping them: {}", points, self.name, err);
+                        let mut state = self.lock();
+                        state.spooled = state.spooled.saturating_sub(points);
+                        self.metrics.add_dropped(&self.name, points);
+                        self.report(&state);
+                    }
+                    self.notify.notify_one();
+                }
+                SpoolOp::Prepend(batches, done) => {
+                    let result = spawn_blocking(move || lock_spool(&spool).prepend(&batches))
+                        .await
+                        .unwrap_or_else(|err| Err(io::Error::other(err).into()));
+                    let _ = done.send(result);
+                }
+            }
+        }
+    }
+
+    /// Waits until every queued record is written or the deadline passes, for the shutdown.
+    ///
+    /// What's left in memory then goes in front of the spool file, or is dropped if there is
+    /// none. A batch that's still being written may end up written twice, on the next run.
+    /// Returns the number of dropped records.
+    pub async fn flush(&self, deadline: Instant) -> usize {
+        loop {
+            let idle = self.idle.notified();
+            {
+                let state = self.lock();
+                if state.batches.is_empty() && state.spooled == 0 {
+                    return 0;
+                }
+            }
+            if timeout_at(deadline, idle).await.is_err() {
+                break;
+            }
+        }
+
+        let (batches, points) = {
+            let mut state = self.lock();
+            let points = std::mem::take(&mut state.points);
+            state.closed = true;
+            (state.batches.drain(..).collect::<Vec<_>>(), points)
+        };
+
+        let dropped = match &self.spool_ops {
+            Some(spool_ops) if points > 0 => {
+                // Older than the spooled ones, so they go first. Queued after the pending
+                // appends, which are done by the time it's answered.
+                let (done, result) = oneshot::channel();
+                let _ = spool_ops.send(SpoolOp::Prepend(batches, done));
+                match result.await {
+                    Ok(Ok(())) => {
+                        self.lock().spooled += points;
+                        0
+                    }
+                    Ok(Err(err)) => {
+                        eprintln!("Error spooling the queue of sink {}: {}", self.name, err);
+                        points
+                    }
+                    Err(_) => points,
+                }
+            }
+            _ => points,
+        };
+
+        let state = self.lock();
+        if state.spooled > 0 {
+            eprintln!("Sink {} has {} spooled points left for the next run", self.name, state.spooled);
+        }
+        if dropped > 0 {
+            eprintln!("Sink {} is still failing, dropping its {} queued points", self.name, dropped);
+            self.metrics.add_dropped(&self.name, dropped);
+        }
+        self.report(&state);
+        dropped
+    }
+
+    /// Moves the oldest spooled batches that fit into memory. Returns whether any were moved.
+    async fn unspool(&self) -> bool {
+        let spool = match &self.spool {
+            Some(spool) => spool.clone(),
+            None => return false,
+        };
+        let max_points = self.max_points;
+        let result = spawn_blocking(move || lock_spool(&spool).read(max_points))
+            .await
+            .unwrap_or_else(|err| Err(io::Error::other(err).into()));
+
+        let mut state = self.lock();
+        let moved = match result {
+            Ok(batches) => {
+                let moved = !batches.is_empty();
+                for batch in batches {
+                    let points = batch.records.len();
+                    state.spooled = state.spooled.saturating_sub(points);
+                    state.points += points;
+                    state.batches.push_back(Arc::new(batch));
+                }
+                moved
+            }
+            Err(err) => {
+                eprintln!("Error reading the spool file of sink {}, dropped it: {}", self.name, err);
+                self.metrics.add_dropped(&self.name, state.spooled);
+                state.spooled = 0;
+                false
+            }
+        };
+        self.report(&state);
+        moved
+    }
+
+    fn report(&self, state: &State) {
+        self.metrics.set_queue(&self.name, state.points, state.spooled);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("Sink queue lock poisoned")
+    }
+}
+
+/// Newline-delimited batches, of which the ones before `offset` are already read back.
+struct Spool {
+    path: PathBuf,
+    /// Bytes at the start of the file that are back in memory
+    offset: u64,
+}
+
+impl Spool {
+    /// Opens the spool file of an earlier run and counts the records that are left in it.
+    fn open(path: PathBuf) -> Result<(Self, usize), SinkError> {
+        let offset = fs::read_to_string(offset_path(&path))
+            .ok()
+            .and_then(|offset| offset.trim().parse().ok())
+            .unwrap_or(0);
+        let mut spool = Spool { path, offset };
+
+        let len = match fs::metadata(&spool.path) {
+            Ok(metadata) => metadata.len(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                spool.set_offset(0)?;
+                return Ok((spool, 0));
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if spool.offset > len {
+            spool.set_offset(0)?;
+        }
+
+        let mut file = File::open(&spool.path)?;
+        // A crash may have cut the last line short, the next append gets a line of its own
+        if len > 0 {
+            let mut last = [0];
+            file.seek(SeekFrom::Start(len - 1))?;
+            file.read_exact(&mut last)?;
+            if last[0] != b'\n' {
+                OpenOptions::new().append(true).open(&spool.path)?.write_all(b"\n")?;
+            }
+        }
+
+        file.seek(SeekFrom::Start(spool.offset))?;
+        let mut points = 0;
+        for line in BufReader::new(file).lines() {
+            if let Some(batch) = spool.parse(&line?) {
+                points += batch.records.len();
+            }
+        }
+        Ok((spool, points))
+    }
+
+    fn append(&mut self, batch: &RecordBatch) -> Result<(), SinkError> {
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let mut line = serde_json::to_vec(batch)?;
+        line.push(b'\n');
+        file.write_all(&line)?;
+        Ok(())
+    }
+
+    /// Reads the next batches up to `max_points`, but at least one. The file is removed once
+    /// it's read to the end. Dropped if it can't be read.
+    fn read(&mut self, max_points: usize) -> Result<Vec<RecordBatch>, SinkError> {
+        self.read_next(max_points).inspect_err(|_| {
+            let _ = fs::remove_file(&self.path);
+            let _ = self.set_offset(0);
+        })
+    }
+
+    fn read_next(&mut self, max_points: usize) -> Result<Vec<RecordBatch>, SinkError> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut reader = BufReader::new(file);
+        reader.seek(SeekFrom::Start(self.offset))?;
+
+        let mut batches = Vec::new();
+        let mut points = 0;
+        let mut offset = self.offset;
+        let mut line = String::new();
+        let at_end = loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 {
+                break true;
+            }
+            if let Some(batch) = self.parse(&line) {
+                if points + batch.records.len() > max_points && !batches.is_empty() {
+                    break false;
+                }
+                points += batch.records.len();
+                batches.push(batch);
+            }
+            offset += read as u64;
+        };
+
+        if at_end {
+            fs::remove_file(&self.path)?;
+            self.set_offset(0)?;
+        } else {
+            self.set_offset(offset)?;
+        }
+        Ok(batches)
+    }
+
+    /// Replaces the file with the batches followed by its unread part, through a temporary
+    /// file so a crash leaves either version.
+    fn prepend(&mut self, batches: &[Arc<RecordBatch>]) -> Result<(), SinkError> {
+        let temp = self.path.with_extension("ndjson.tmp");
+        let mut writer = BufWriter::new(File::create(&temp)?);
+        for batch in batches {
+            serde_json::to_writer(&mut writer, &**batch)?;
+            writer.write_all(b"\n")?;
+        }
+        match File::open(&self.path) {
+            Ok(mut file) => {
+                file.seek(SeekFrom::Start(self.offset))?;
+                io::copy(&mut file, &mut writer)?;
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&temp, &self.path)?;
+        self.set_offset(0)
+    }
+
+    fn parse(&self, line: &str) -> Option<RecordBatch> {
+        if line.trim().is_empty() {
+            return None;
+        }
+        match serde_json::from_str(line) {
+            Ok(batch) => Some(batch),
+            // Most likely cut short by a crash, the other lines are still fine
+            Err(err) => {
+                eprintln!("Skipping a broken line of the spool file {}: {}", self.path.display(), err);
+                None
+            }
+        }
+    }
+
+    fn set_offset(&mut self, offset: u64) -> Result<(), SinkError> {
+        self.offset = offset;
+        let path = offset_path(&self.path);
+        if offset > 0 {
+            fs::write(path, offset.to_string())?;
+        } else if let Err(err) = fs::remove_file(path) {
+            if err.kind() != io::ErrorKind::NotFound {
+                return Err(err.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn offset_path(path: &Path) -> PathBuf {
+    path.with_extension("ndjson.offset")
+}
+
+fn lock_spool(spool: &Mutex<Spool>) -> MutexGuard<'_, Spool> {
+    spool.lock().expect("Spool lock poisoned")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use async_trait::async_trait;
+    use chrono::Utc;
+
+    use crate::records::{Record, SnapshotReading};
+
+    /// Keeps the game ids of the written snapshots, fails while `down` is set and rejects the
+    /// game id 0 for good.
+    #[derive(Default)]
+    struct FlakySink {
+        down: AtomicBool,
+        written: Mutex<Vec<u64>>,
+    }
+
+    #[async_trait]
+    impl Sink for FlakySink {
+        async fn write(&self, batch: &RecordBatch) -> Result<(), SinkError> {
+            if self.down.load(Ordering::SeqCst) {
+                return Err(SinkError::Io(io::Error::other("down")));
+            }
+            let rejected = |record: &Record| matches!(record, Record::Snapshot(reading) if reading.game_id == 0);
+            if batch.records.iter().any(rejected) {
+                return Err(SinkError::Http {
+                    status: reqwest::StatusCode::BAD_REQUEST,
+                    body: "missing tag value".to_string(),
+                });
+            }
+            let mut written = self.written.lock().unwrap();
+            for record in &batch.records {
+                if let Record::Snapshot(reading) = record {
+                    written.push(reading.game_id);
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn batch(game_id: u64) -> Arc<RecordBatch> {
+        let data = crate::fixture("snapshot_rush");
+        let mut reading = SnapshotReading::new(Utc::now(), "guid", &data.snapshot);
+        reading.game_id = game_id;
+        Arc::new(RecordBatch {
+            tags: Default::default(),
+            server_name: None,
+            records: vec![Record::Snapshot(reading)],
+        })
+    }
+
+    async fn recover(sink: &FlakySink, metrics: &Metrics) {
+        sink.down.store(false, Ordering::SeqCst);
+        for _ in 0..600 {
+            sleep(Duration::from_secs(1)).await;
+            let text = metrics.render();
+            if text.contains("bflogger_sink_queued_points{sink=\"test\",location=\"memory\"} 0\n")
+                && text.contains("bflogger_sink_queued_points{sink=\"test\",location=\"spool\"} 0\n")
+            {
+                return;
+            }
+        }
+        panic!("queue wasn't written:\n{}", metrics.render());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drops_oldest_without_spool() {
+        let sink = Arc::new(FlakySink::default());
+        sink.down.store(true, Ordering::SeqCst);
+        let metrics = Arc::new(Metrics::default());
+        let config = QueueConfig {
+            max_points: 3,
+            spool_directory: None,
+        };
+        let queue = SinkQueue::start("test", sink.clone(), &config, metrics.clone()).unwrap();
+
+        for game_id in 1..=5 {
+            queue.push(batch(game_id));
+        }
+        recover(&sink, &metrics).await;
+
+        // The front batch is kept since its write may be under way
+        assert_eq!(vec![1, 4, 5], *sink.written.lock().unwrap());
+        assert!(metrics.render().contains("bflogger_sink_dropped_points_total{sink=\"test\"} 2\n"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drops_rejected() {
+        let sink = Arc::new(FlakySink::default());
+        sink.down.store(true, Ordering::SeqCst);
+        let metrics = Arc::new(Metrics::default());
+        let config = QueueConfig {
+            max_points: 10,
+            spool_directory: None,
+        };
+        let queue = SinkQueue::start("test", sink.clone(), &config, metrics.clone()).unwrap();
+
+        // Retried while the sink is down, dropped once it answers
+        queue.push(batch(1));
+        queue.push(batch(0));
+        queue.push(batch(2));
+        recover(&sink, &metrics).await;
+
+        assert_eq!(vec![1, 2], *sink.written.lock().unwrap());
+        assert!(metrics.render().contains("bflogger_sink_dropped_points_total{sink=\"test\"} 1\n"));
+        assert_eq!(0, queue.flush(Instant::now() + Duration::from_secs(5)).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flush() {
+        let sink = Arc::new(FlakySink::default());
+        let metrics = Arc::new(Metrics::default());
+        let config = QueueConfig {
+            max_points: 10,
+            spool_directory: None,
+        };
+        let queue = SinkQueue::start("test", sink.clone(), &config, metrics.clone()).unwrap();
+
+        queue.push(batch(1));
+        queue.push(batch(2));
+        assert_eq!(0, queue.flush(Instant::now() + Duration::from_secs(5)).await);
+        assert_eq!(vec![1, 2], *sink.written.lock().unwrap());
+
+        sink.down.store(true, Ordering::SeqCst);
+        queue.push(batch(3));
+        let start = Instant::now();
+        assert_eq!(1, queue.flush(start + Duration::from_secs(5)).await);
+        assert_eq!(Duration::from_secs(5), start.elapsed());
+        assert!(metrics.render().contains("bflogger_sink_dropped_points_total{sink=\"test\"} 1\n"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn spools_overflow_in_order() {
+        let directory = std::env::temp_dir().join(format!("bflogger-queue-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        let sink = Arc::new(FlakySink::default());
+        sink.down.store(true, Ordering::SeqCst);
+        let metrics = Arc::new(Metrics::default());
+        let config = QueueConfig {
+            max_points: 2,
+            spool_directory: Some(directory.clone()),
+        };
+        let queue = SinkQueue::start("test", sink.clone(), &config, metrics.clone()).unwrap();
+
+        for game_id in 1..=5 {
+            queue.push(batch(game_id));
+        }
+        assert!(metrics.render().contains("bflogger_sink_queued_points{sink=\"test\",location=\"spool\"} 3\n"));
+        recover(&sink, &metrics).await;
+
+        assert_eq!(vec![1, 2, 3, 4, 5], *sink.written.lock().unwrap());
+        assert!(metrics.render().contains("bflogger_sink_dropped_points_total{sink=\"test\"} 0\n"));
+        assert!(!directory.join("test.ndjson").exists());
+
+        // The memory queue is spooled on shutdown and written by the next run
+        sink.down.store(true, Ordering::SeqCst);
+        queue.push(batch(6));
+        queue.push(batch(7));
+        queue.push(batch(8));
+        assert_eq!(0, queue.flush(Instant::now() + Duration::from_secs(5)).await);
+        sink.down.store(false, Ordering::SeqCst);
+        let metrics = Arc::new(Metrics::default());
+        let _next_run = SinkQueue::start("test", sink.clone(), &config, metrics.clone()).unwrap();
+        recover(&sink, &metrics).await;
+        assert_eq!(vec![1, 2, 3, 4, 5, 6, 7, 8], sink.written.lock().unwrap()[..8].to_vec());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn spool_resumes_at_offset() {
+        let directory = std::env::temp_dir().join(format!("bflogger-spool-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("test.ndjson");
+        let game_ids = |batches: Vec<RecordBatch>| -> Vec<u64> {
+            batches
+                .iter()
+                .flat_map(|batch| &batch.records)
+                .filter_map(|record| match record {
+                    Record::Snapshot(reading) => Some(reading.game_id),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        let (mut spool, points) = Spool::open(path.clone()).unwrap();
+        assert_eq!(0, points);
+        for game_id in 1..=5 {
+            spool.append(&batch(game_id)).unwrap();
+        }
+        assert_eq!(vec![1, 2], game_ids(spool.read(2).unwrap()));
+
+        // A crash cut the last append short, the next run starts after the read batches
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{\"tags\":").unwrap();
+        let (mut spool, points) = Spool::open(path.clone()).unwrap();
+        assert_eq!(3, points);
+        spool.append(&batch(6)).unwrap();
+        assert_eq!(vec![3, 4, 5, 6], game_ids(spool.read(10).unwrap()));
+        assert!(!path.exists());
+        assert!(!offset_path(&path).exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,711 @@
+use std::{
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::{Sink, SinkError};
+use crate::records::{Record, RecordBatch};
+
+/// Schema changes, applied in order and tracked in `PRAGMA user_version`. Never edit a migration
+/// that has been released, add a new one.
+///
+/// Times are stored as unix timestamps in seconds.
+const MIGRATIONS: &[&str] = &[
+    // 1: one table per measurement, the rounds and sessions get a row once they're over
+    "
+    CREATE TABLE snapshots (
+        time INTEGER NOT NULL,
+        server_guid TEXT NOT NULL,
+        game_id INTEGER NOT NULL,
+        game_mode TEXT NOT NULL,
+        current_map TEXT NOT NULL,
+        map_variant INTEGER NOT NULL,
+        max_players INTEGER NOT NULL,
+        waiting_players INTEGER NOT NULL,
+        players INTEGER NOT NULL,
+        round_time INTEGER NOT NULL,
+        round_running INTEGER NOT NULL,
+        tags TEXT NOT NULL DEFAULT '{}'
+    );
+    CREATE INDEX snapshots_server_guid_time ON snapshots (server_guid, time);
+
+    CREATE TABLE teams (
+        time INTEGER NOT NULL,
+        server_guid TEXT NOT NULL,
+        game_mode TEXT NOT NULL,
+        team INTEGER NOT NULL,
+        tickets INTEGER,
+        tickets_max INTEGER,
+        bases INTEGER,
+        bases_max INTEGER,
+        kills INTEGER,
+        kills_max INTEGER,
+        destroyed_crates INTEGER,
+        carrier_health INTEGER,
+        score INTEGER,
+        score_max INTEGER,
+        flags INTEGER,
+        flags_max INTEGER,
+        rounds INTEGER,
+        rounds_max INTEGER,
+        tags TEXT NOT NULL DEFAULT '{}'
+    );
+    CREATE INDEX teams_server_guid_time ON teams (server_guid, time);
+
+    CREATE TABLE players (
+        time INTEGER NOT NULL,
+        server_guid TEXT NOT NULL,
+        persona_id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        clan_tag TEXT NOT NULL,
+        team INTEGER NOT NULL,
+        game_id INTEGER NOT NULL,
+        score INTEGER NOT NULL,
+        kills INTEGER NOT NULL,
+        deaths INTEGER NOT NULL,
+        kd REAL NOT NULL,
+        squad INTEGER NOT NULL,
+        rank INTEGER NOT NULL,
+        role INTEGER NOT NULL,
+        tags TEXT NOT NULL DEFAULT '{}'
+    );
+    CREATE INDEX players_persona_id_time ON players (persona_id, time);
+    CREATE INDEX players_time ON players (time);
+
+    CREATE TABLE round_events (
+        time INTEGER NOT NULL,
+        server_guid TEXT NOT NULL,
+        event TEXT NOT NULL,
+        game_id INTEGER NOT NULL,
+        current_map TEXT NOT NULL,
+        previous_map TEXT,
+        winner INTEGER,
+        tags TEXT NOT NULL DEFAULT '{}'
+    );
+    CREATE INDEX round_events_time ON round_events (time);
+
+    CREATE TABLE rounds (
+        ended_at INTEGER NOT NULL,
+        started_at INTEGER NOT NULL,
+        server_guid TEXT NOT NULL,
+        game_id INTEGER NOT NULL,
+        game_mode TEXT NOT NULL,
+        current_map TEXT NOT NULL,
+        duration INTEGER NOT NULL,
+        winner INTEGER,
+        team1_score INTEGER,
+        team2_score INTEGER,
+        scores TEXT NOT NULL,
+        peak_players INTEGER NOT NULL,
+        top_scorers TEXT NOT NULL,
+        tags TEXT NOT NULL DEFAULT '{}'
+    );
+    CREATE INDEX rounds_server_guid_ended_at ON rounds (server_guid, ended_at);
+
+    CREATE TABLE session_events (
+        time INTEGER NOT NULL,
+        server_guid TEXT NOT NULL,
+        event TEXT NOT NULL,
+        persona_id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        team INTEGER NOT NULL,
+        previous_team INTEGER,
+        tags TEXT NOT NULL DEFAULT '{}'
+    );
+    CREATE INDEX session_events_time ON session_events (time);
+
+    CREATE TABLE sessions (
+        left_at INTEGER NOT NULL,
+        joined_at INTEGER NOT NULL,
+        server_guid TEXT NOT NULL,
+        persona_id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        clan_tag TEXT NOT NULL,
+        team INTEGER NOT NULL,
+        duration INTEGER NOT NULL,
+        team_switches INTEGER NOT NULL,
+        score INTEGER NOT NULL,
+        kills INTEGER NOT NULL,
+        deaths INTEGER NOT NULL,
+        tags TEXT NOT NULL DEFAULT '{}'
+    );
+    CREATE INDEX sessions_persona_id ON sessions (persona_id);
+    CREATE INDEX sessions_left_at ON sessions (left_at);
+    ",
+    // 2: the outcome of every poll
+    "
+    CREATE TABLE poll_status (
+        time INTEGER NOT NULL,
+        server_guid TEXT NOT NULL,
+        status TEXT NOT NULL,
+        latency_ms INTEGER NOT NULL,
+        consecutive_failures INTEGER NOT NULL,
+        error TEXT,
+        tags TEXT NOT NULL DEFAULT '{}'
+    );
+    CREATE INDEX poll_status_server_guid_time ON poll_status (server_guid, time);
+    ",
+    // 3: the rotation of the servers, and how far the rounds got
+    "
+    CREATE TABLE rotations (
+        time INTEGER NOT NULL,
+        server_guid TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        current_map TEXT NOT NULL,
+        game_mode TEXT NOT NULL,
+        tags TEXT NOT NULL DEFAULT '{}'
+    );
+    CREATE INDEX rotations_server_guid_time ON rotations (server_guid, time);
+
+    ALTER TABLE rounds ADD COLUMN progress REAL;
+    ",
+    // 4: the name of the server on Battlelog
+    "
+    ALTER TABLE snapshots ADD COLUMN server_name TEXT;
+    ",
+    // 5: the server name on the other tables too
+    "
+    ALTER TABLE teams ADD COLUMN server_name TEXT;
+    ALTER TABLE players ADD COLUMN server_name TEXT;
+    ALTER TABLE round_events ADD COLUMN server_name TEXT;
+    ALTER TABLE rounds ADD COLUMN server_name TEXT;
+    ALTER TABLE session_events ADD COLUMN server_name TEXT;
+    ALTER TABLE sessions ADD COLUMN server_name TEXT;
+    ALTER TABLE poll_status ADD COLUMN server_name TEXT;
+    ALTER TABLE rotations ADD COLUMN server_name TEXT;
+    ",
+];
+
+/// The tables and their time column, for the retention pruning.
+const TABLES: &[(&str, &str)] = &[
+    ("snapshots", "time"),
+    ("teams", "time"),
+    ("players", "time"),
+    ("round_events", "time"),
+    ("rounds", "ended_at"),
+    ("session_events", "time"),
+    ("sessions", "left_at"),
+    ("poll_status", "time"),
+    ("rotations", "time"),
+];
+
+/// How often the rows older than the retention are deleted.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Keeps the history in an embedded SQLite database, for setups without a database server.
+///
+/// The database runs in WAL mode, so the query helpers can read while the logger writes.
+/// Writes run on the blocking threads, SQLite calls block.
+pub struct SqliteSink {
+    db: Arc<Mutex<Db>>,
+}
+
+struct Db {
+    connection: Connection,
+    retention: Option<chrono::Duration>,
+    last_prune: Option<Instant>,
+}
+
+/// Average and peak player count of a time bucket, see [`SqliteSink::player_count`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerCount {
+    pub time: DateTime<Utc>,
+    pub average: f64,
+    pub peak: u16,
+}
+
+/// A finished round, see [`SqliteSink::round_history`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoundRow {
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub current_map: String,
+    pub game_mode: String,
+    pub duration: u32,
+    pub winner: Option<u8>,
+    pub scores: String,
+    pub peak_players: u16,
+    /// How far the round got, `None` for the rounds logged before it was recorded
+    pub progress: Option<f64>,
+}
+
+/// A map of the rotation, see [`SqliteSink::rotation`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RotationRow {
+    /// When the server switched to this rotation, or the logger first saw it
+    pub time: DateTime<Utc>,
+    pub position: u32,
+    pub current_map: String,
+    pub game_mode: String,
+}
+
+/// Average player count while a map was being played, see [`SqliteSink::map_population`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapPopulation {
+    pub current_map: String,
+    pub game_mode: String,
+    pub average: f64,
+}
+
+/// What a player did over all of their finished sessions, see [`SqliteSink::player_totals`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerTotals {
+    pub persona_id: u64,
+    pub name: String,
+    pub sessions: u32,
+    /// Time on the server in seconds
+    pub play_time: i64,
+    pub score: u64,
+    pub kills: u64,
+    pub deaths: u64,
+}
+
+impl SqliteSink {
+    /// Opens or creates the database and applies the pending migrations. With `retention_days`
+    /// the older rows are deleted right away and then every hour.
+    pub fn open(path: &Path, retention_days: Option<u32>) -> Result<Self, SinkError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut connection = Connection::open(path)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+        connection.busy_timeout(Duration::from_secs(5))?;
+        migrate(&mut connection)?;
+
+        let mut db = Db {
+            connection,
+            retention: retention_days.map(|days| chrono::Duration::days(i64::from(days))),
+            last_prune: None,
+        };
+        db.prune()?;
+
+        Ok(Self {
+            db: Arc::new(Mutex::new(db)),
+        })
+    }
+
+    /// Average and peak player count of the server since `since`, in buckets of `bucket` seconds.
+    pub fn player_count(&self, server_guid: &str, since: DateTime<Utc>, bucket: u32) -> Result<Vec<PlayerCount>, SinkError> {
+        let db = self.db.lock().expect("SQLite sink lock poisoned");
+        let mut statement = db.connection.prepare(
+            "SELECT time / ?1 * ?1 AS bucket, AVG(players), MAX(players)
+            FROM snapshots
+            WHERE server_guid = ?2 AND time >= ?3
+            GROUP BY bucket
+            ORDER BY bucket",
+        )?;
+        let rows = statement.query_map(params![bucket.max(1), server_guid, since.timestamp()], |row| {
+            Ok(PlayerCount {
+                time: timestamp(row.get(0)?),
+                average: row.get(1)?,
+                peak: row.get(2)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// The last `limit` rounds of the server, newest first.
+    pub fn round_history(&self, server_guid: &str, limit: u32) -> Result<Vec<RoundRow>, SinkError> {
+        let db = self.db.lock().expect("SQLite sink lock poisoned");
+        let mut statement = db.connection.prepare(
+            "SELECT started_at, ended_at, current_map, game_mode, duration, winner, scores, peak_players, progress
+            FROM rounds
+            WHERE server_guid = ?1
+            ORDER BY ended_at DESC
+            LIMIT ?2",
+        )?;
+        let rows = statement.query_map(params![server_guid, limit], round_row)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// The rounds of the server that started since `since`, oldest first.
+    pub fn rounds_since(&self, server_guid: &str, since: DateTime<Utc>) -> Result<Vec<RoundRow>, SinkError> {
+        let db = self.db.lock().expect("SQLite sink lock poisoned");
+        let mut statement = db.connection.prepare(
+            "SELECT started_at, ended_at, current_map, game_mode, duration, winner, scores, peak_players, progress
+            FROM rounds
+            WHERE server_guid = ?1 AND started_at >= ?2
+            ORDER BY ended_at",
+        )?;
+        let rows = statement.query_map(params![server_guid, since.timestamp()], round_row)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// The latest rotation written for the server, in the order the maps are played.
+    pub fn rotation(&self, server_guid: &str) -> Result<Vec<RotationRow>, SinkError> {
+        let db = self.db.lock().expect("SQLite sink lock poisoned");
+        let mut statement = db.connection.prepare(
+            "SELECT time, position, current_map, game_mode
+            FROM rotations
+            WHERE server_guid = ?1 AND time = (SELECT MAX(time) FROM rotations WHERE server_guid = ?1)
+            ORDER BY position",
+        )?;
+        let rows = statement.query_map(params![server_guid], |row| {
+            Ok(RotationRow {
+                time: timestamp(row.get(0)?),
+                position: row.get(1)?,
+                current_map: row.get(2)?,
+                game_mode: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Average player count of the server per map and game mode since `since`, counting only
+    /// the snapshots of running rounds.
+    pub fn map_population(&self, server_guid: &str, since: DateTime<Utc>) -> Result<Vec<MapPopulation>, SinkError> {
+        let db = self.db.lock().expect("SQLite sink lock poisoned");
+        let mut statement = db.connection.prepare(
+            "SELECT current_map, game_mode, AVG(players)
+            FROM snapshots
+            WHERE server_guid = ?1 AND time >= ?2 AND round_running
+            GROUP BY current_map, game_mode",
+        )?;
+        let rows = statement.query_map(params![server_guid, since.timestamp()], |row| {
+            Ok(MapPopulation {
+                current_map: row.get(0)?,
+                game_mode: row.get(1)?,
+                average: row.get(2)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// The `limit` best players by score over their finished sessions, on one or every server.
+    pub fn player_totals(&self, server_guid: Option<&str>, limit: u32) -> Result<Vec<PlayerTotals>, SinkError> {
+        let db = self.db.lock().expect("SQLite sink lock poisoned");
+        let mut statement = db.connection.prepare(
+            "SELECT persona_id, (SELECT s.name FROM sessions s WHERE s.persona_id = sessions.persona_id ORDER BY s.left_at DESC LIMIT 1),
+                COUNT(*), SUM(duration), SUM(score), SUM(kills), SUM(deaths)
+            FROM sessions
+            WHERE ?1 IS NULL OR server_guid = ?1
+            GROUP BY persona_id
+            ORDER BY SUM(score) DESC
+            LIMIT ?2",
+        )?;
+        let rows = statement.query_map(params![server_guid, limit], |row| {
+            Ok(PlayerTotals {
+                persona_id: row.get::<_, i64>(0)? as u64,
+                name: row.get(1)?,
+                sessions: row.get(2)?,
+                play_time: row.get(3)?,
+                score: row.get::<_, i64>(4)? as u64,
+                kills: row.get::<_, i64>(5)? as u64,
+                deaths: row.get::<_, i64>(6)? as u64,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
+
+impl Db {
+    fn insert(&mut self, batch: &RecordBatch) -> Result<(), SinkError> {
+        let tags = serde_json::to_string(&batch.tags)?;
+        let server_name = batch.server_name.as_deref();
+        let tx = self.connection.transaction()?;
+
+        for record in &batch.records {
+            match record {
+                Record::Snapshot(r) => {
+                    tx.prepare_cached(
+                        "INSERT INTO snapshots (time, server_guid, game_id, game_mode, current_map, map_variant, max_players,
+                            waiting_players, players, round_time, round_running, server_name, tags)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                    )?.execute(params![
+                        r.time.timestamp(), r.server_guid, r.game_id as i64, r.game_mode, r.current_map, r.map_variant,
+                        r.max_players, r.waiting_players, r.players, r.round_time, r.round_running, server_name, tags,
+                    ])?;
+                }
+                Record::Team(r) => {
+                    tx.prepare_cached(
+                        "INSERT INTO teams (time, server_guid, game_mode, team, tickets, tickets_max, bases, bases_max, kills,
+                            kills_max, destroyed_crates, carrier_health, score, score_max, flags, flags_max, rounds, rounds_max,
+                            server_name, tags)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20)",
+                    )?.execute(params![
+                        r.time.timestamp(), r.server_guid, r.game_mode, r.team, r.tickets, r.tickets_max, r.bases,
+                        r.bases_max, r.kills, r.kills_max, r.destroyed_crates, r.carrier_health, r.score, r.score_max,
+                        r.flags, r.flags_max, r.rounds, r.rounds_max, server_name, tags,
+                    ])?;
+                }
+                Record::Player(r) => {
+                    tx.prepare_cached(
+                        "INSERT INTO players (time, server_guid, persona_id, name, clan_tag, team, game_id, score, kills, deaths,
+                            kd, squad, rank, role, server_name, tags)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+                    )?.execute(params![
+                        r.time.timestamp(), r.server_guid, r.persona_id as i64, r.name, r.clan_tag.as_deref().unwrap_or(""), r.team,
+                        r.game_id as i64, r.score, r.kills, r.deaths, r.kd, r.squad, r.rank, r.role, server_name, tags,
+                    ])?;
+                }
+                Record::RoundEvent(r) => {
+                    tx.prepare_cached(
+                        "INSERT INTO round_events (time, server_guid, event, game_id, current_map, previous_map, winner,
+                            server_name, tags)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                    )?.execute(params![
+                        r.time.timestamp(), r.server_guid, r.event, r.game_id as i64, r.current_map, r.previous_map,
+                        r.winner, server_name, tags,
+                    ])?;
+                }
+                Record::RoundSummary(r) => {
+                    tx.prepare_cached(
+                        "INSERT INTO rounds (ended_at, started_at, server_guid, game_id, game_mode, current_map, duration, winner,
+                            team1_score, team2_score, scores, peak_players, top_scorers, progress, server_name, tags)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+                    )?.execute(params![
+                        r.time.timestamp(), r.started_at, r.server_guid, r.game_id as i64, r.game_mode, r.current_map,
+                        r.duration, r.winner, r.team1_score, r.team2_score, r.scores, r.peak_players, r.top_scorers,
+                        r.progress, server_name, tags,
+                    ])?;
+                }
+                Record::SessionEvent(r) => {
+                    tx.prepare_cached(
+                        "INSERT INTO session_events (time, server_guid, event, persona_id, name, team, previous_team,
+                            server_name, tags)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                    )?.execute(params![
+                        r.time.timestamp(), r.server_guid, r.event, r.persona_id as i64, r.name, r.team, r.previous_team,
+                        server_name, tags,
+                    ])?;
+
+                    // Only the leave events carry the totals of the session
+                    if let Some(joined_at) = r.joined_at {
+                        tx.prepare_cached(
+                            "INSERT INTO sessions (left_at, joined_at, server_guid, persona_id, name, clan_tag, team, duration,
+                                team_switches, score, kills, deaths, server_name, tags)
+                            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                        )?.execute(params![
+                            r.left_at.unwrap_or_else(|| r.time.timestamp()), joined_at, r.server_guid, r.persona_id as i64, r.name,
+                            r.clan_tag.as_deref().unwrap_or(""), r.team, r.duration.unwrap_or(0),
+                            r.team_switches.unwrap_or(0), r.score.unwrap_or(0), r.kills.unwrap_or(0),
+                            r.deaths.unwrap_or(0), server_name, tags,
+                        ])?;
+                    }
+                }
+                Record::PollStatus(r) => {
+                    tx.prepare_cached(
+                        "INSERT INTO poll_status (time, server_guid, status, latency_ms, consecutive_failures, error,
+                            server_name, tags)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                    )?.execute(params![
+                        r.time.timestamp(), r.server_guid, r.status, r.latency_ms as i64, r.consecutive_failures, r.error,
+                        server_name, tags,
+                    ])?;
+                }
+                Record::Rotation(r) => {
+                    tx.prepare_cached(
+                        "INSERT INTO rotations (time, server_guid, position, current_map, game_mode, server_name, tags)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    )?.execute(params![
+                        r.time.timestamp(), r.server_guid, r.position, r.current_map, r.game_mode, server_name, tags,
+                    ])?;
+                }
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Deletes the rows older than the retention, at most once per [`PRUNE_INTERVAL`].
+    fn prune(&mut self) -> Result<(), SinkError> {
+        let retention = match self.retention {
+            Some(retention) => retention,
+            None => return Ok(()),
+        };
+        if self.last_prune.is_some_and(|last_prune| last_prune.elapsed() < PRUNE_INTERVAL) {
+            return Ok(());
+        }
+        self.last_prune = Some(Instant::now());
+
+        let cutoff = (Utc::now() - retention).timestamp();
+        let mut deleted = 0;
+        for (table, column) in TABLES {
+            deleted += self.connection.execute(&format!("DELETE FROM {} WHERE {} < ?1", table, column), [cutoff])?;
+        }
+        if deleted > 0 {
+            eprintln!("Pruned {} rows older than {} days from SQLite", deleted, retention.num_days());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for SqliteSink {
+    async fn write(&self, batch: &RecordBatch) -> Result<(), SinkError> {
+        let db = self.db.clone();
+        let batch = batch.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut db = db.lock().expect("SQLite sink lock poisoned");
+            db.insert(&batch)?;
+            db.prune()
+        })
+        .await
+        .map_err(|err| SinkError::Io(std::io::Error::other(err)))?
+    }
+}
+
+fn migrate(connection: &mut Connection) -> Result<(), SinkError> {
+    let current: i64 = connection
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .optional()?
+        .unwrap_or(0);
+
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        let version = version as i64 + 1;
+        eprintln!("Applying SQLite migration {}", version);
+
+        let tx = connection.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", version)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+fn round_row(row: &rusqlite::Row) -> rusqlite::Result<RoundRow> {
+    Ok(RoundRow {
+        started_at: timestamp(row.get(0)?),
+        ended_at: timestamp(row.get(1)?),
+        current_map: row.get(2)?,
+        game_mode: row.get(3)?,
+        duration: row.get(4)?,
+        winner: row.get(5)?,
+        scores: row.get(6)?,
+        peak_players: row.get(7)?,
+        progress: row.get(8)?,
+    })
+}
+
+fn timestamp(secs: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(secs, 0).single().unwrap_or_else(Utc::now)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use battlelog::{GameMode, Map, RotationEntry};
+    use chrono::Duration as ChronoDuration;
+
+    use super::*;
+    use crate::poll::PollStatus;
+    use crate::records::{rotation_readings, PollStatusReading, RoundSummaryReading, SessionEventReading, SnapshotReading};
+    use crate::round::RoundTracker;
+    use crate::session::{PlayerSession, SessionEvent};
+
+    #[test]
+    fn write_query_and_prune() {
+        let data = crate::fixture("snapshot_conquest");
+        let path = std::env::temp_dir().join(format!("bflogger-sqlite-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let guid = "4d0151b3-81ff-4268-b4e8-5e60d5bc8765";
+        let now = Utc::now();
+        let old = now - ChronoDuration::days(40);
+
+        let mut tracker = RoundTracker::default();
+        tracker.update(now - ChronoDuration::minutes(30), &data.snapshot);
+        let mut between = data.snapshot.clone();
+        between.conquest = None;
+        let summary = match tracker.update(now, &between).pop() {
+            Some(crate::round::RoundEvent::Ended(summary)) => summary,
+            events => panic!("unexpected events {:?}", events),
+        };
+
+        let session = PlayerSession {
+            persona_id: 806262072,
+            name: "xfileFIN".to_string(),
+            clan_tag: "BFOX".to_string(),
+            team: 1,
+            joined_at: now - ChronoDuration::hours(1),
+            left_at: now - ChronoDuration::seconds(30),
+            team_switches: 0,
+            score: 3120,
+            kills: 20,
+            deaths: 5,
+        };
+        let rotation: Vec<RotationEntry> = ["MP_Prison", "MP_Abandoned", "XP0_Metro"]
+            .iter()
+            .map(|map| RotationEntry { map: Map::from(*map), game_mode: GameMode::ConquestLarge })
+            .collect();
+        let mut batch = RecordBatch {
+            tags: BTreeMap::new(),
+            server_name: Some("=BFX= BattleFox #1".to_string()),
+            records: vec![
+                Record::Snapshot(SnapshotReading::new(old, guid, &data.snapshot)),
+                Record::Snapshot(SnapshotReading::new(now, guid, &data.snapshot)),
+                Record::RoundSummary(RoundSummaryReading::new(guid, &summary)),
+                Record::SessionEvent(SessionEventReading::new(now, guid, &SessionEvent::Left(session.clone()))),
+                Record::SessionEvent(SessionEventReading::new(now, guid, &SessionEvent::Left(session))),
+                Record::PollStatus(PollStatusReading::new(
+                    now,
+                    guid,
+                    PollStatus::Timeout,
+                    Duration::from_secs(10),
+                    3,
+                    Some("request failed: operation timed out".to_string()),
+                )),
+            ],
+        };
+        // An older rotation, only the latest one counts
+        batch.records.extend(rotation_readings(old, guid, &rotation[..1]).into_iter().map(Record::Rotation));
+        batch.records.extend(rotation_readings(now, guid, &rotation).into_iter().map(Record::Rotation));
+
+        let sink = SqliteSink::open(&path, None).unwrap();
+        tokio::runtime::Runtime::new().unwrap().block_on(sink.write(&batch)).unwrap();
+
+        let counts = sink.player_count(guid, old - ChronoDuration::days(1), 300).unwrap();
+        assert_eq!(2, counts.len());
+        assert_eq!(2, counts[1].peak);
+
+        let rounds = sink.round_history(guid, 10).unwrap();
+        assert_eq!(1, rounds.len());
+        assert_eq!("MP_Prison", rounds[0].current_map);
+        assert_eq!(Some(1), rounds[0].winner);
+        assert_eq!(rounds, sink.rounds_since(guid, now - ChronoDuration::hours(1)).unwrap());
+        assert!(rounds[0].progress.is_some());
+
+        let rotation = sink.rotation(guid).unwrap();
+        assert_eq!(3, rotation.len());
+        assert_eq!((2, "XP0_Metro"), (rotation[2].position, rotation[2].current_map.as_str()));
+
+        let population = sink.map_population(guid, now - ChronoDuration::hours(1)).unwrap();
+        assert_eq!(1, population.len());
+        assert_eq!(("MP_Prison", 2.0), (population[0].current_map.as_str(), population[0].average));
+
+        let totals = sink.player_totals(None, 10).unwrap();
+        assert_eq!(1, totals.len());
+        assert_eq!(2, totals[0].sessions);
+        assert_eq!(6240, totals[0].score);
+        assert_eq!(7140, totals[0].play_time);
+        // The session ended when the player was last seen, not when the leave was noticed
+        let left_at: i64 = sink.db.lock().unwrap().connection
+            .query_row("SELECT MAX(left_at) FROM sessions", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!((now - ChronoDuration::seconds(30)).timestamp(), left_at);
+
+        let status: (String, i64, String) = sink.db.lock().unwrap().connection
+            .query_row("SELECT status, latency_ms, server_name FROM poll_status", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap();
+        assert_eq!(("timeout".to_string(), 10000, "=BFX= BattleFox #1".to_string()), status);
+        drop(sink);
+
+        // Reopening doesn't migrate again and prunes the old snapshot
+        let sink = SqliteSink::open(&path, Some(30)).unwrap();
+        assert_eq!(1, sink.player_count(guid, old - ChronoDuration::days(1), 300).unwrap().len());
+        drop(sink);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
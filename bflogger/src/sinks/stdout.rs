@@ -0,0 +1,25 @@
+use std::io::{self, Write};
+
+use async_trait::async_trait;
+
+use super::{Sink, SinkError};
+use crate::records::RecordBatch;
+
+/// Prints the records as newline-delimited JSON, handy for piping into other tools.
+pub struct StdoutSink;
+
+#[async_trait]
+impl Sink for StdoutSink {
+    async fn write(&self, batch: &RecordBatch) -> Result<(), SinkError> {
+        let lines = batch.to_json()?;
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        for line in lines {
+            serde_json::to_writer(&mut stdout, &line)?;
+            stdout.write_all(b"\n")?;
+        }
+        stdout.flush()?;
+        Ok(())
+    }
+}
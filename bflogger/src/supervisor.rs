@@ -0,0 +1,148 @@
+//! Keeps the poll loops running until the logger is asked to stop.
+//!
+//! Every loop runs in its own task under a supervisor that restarts it with backoff if it
+//! panics. On SIGTERM or Ctrl+C the [`Shutdown`] signal tells the loops to stop at their next
+//! await point, which never leaves a batch half written since handing a batch to the sinks
+//! doesn't wait.
+
+use std::{future::Future, time::Duration};
+
+use tokio::{
+    sync::watch,
+    task::JoinHandle,
+    time::{sleep, Instant},
+};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A task that ran this long before panicking is restarted without waiting.
+const HEALTHY_RUN: Duration = Duration::from_secs(5 * 60);
+
+/// Tells the tasks that the logger is shutting down, cloned into every one of them.
+#[derive(Debug, Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    /// The signal and the sender that triggers it.
+    pub fn new() -> (watch::Sender<bool>, Self) {
+        let (sender, receiver) = watch::channel(false);
+        (sender, Self(receiver))
+    }
+
+    /// Waits for the shutdown, which also happens if the sender is dropped.
+    pub async fn wait(&mut self) {
+        let _ = self.0.wait_for(|shutdown| *shutdown).await;
+    }
+
+    /// Runs the future unless the shutdown comes first, `None` means it was cancelled.
+    pub async fn cancel<F: Future>(&mut self, future: F) -> Option<F::Output> {
+        tokio::select! {
+            output = future => Some(output),
+            _ = self.wait() => None,
+        }
+    }
+}
+
+/// Spawns the task made by `task` and spawns a new one whenever it panics, until it returns.
+///
+/// The tasks are expected to return once the shutdown signal is given.
+pub fn supervise<F, T>(name: String, mut shutdown: Shutdown, mut task: F) -> JoinHandle<()>
+where
+    F: FnMut() -> T + Send + 'static,
+    T: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            let started = Instant::now();
+            let err = match tokio::spawn(task()).await {
+                Ok(()) => return,
+                Err(err) => err,
+            };
+
+            if started.elapsed() >= HEALTHY_RUN {
+                backoff = INITIAL_BACKOFF;
+            }
+            eprintln!("Task of {} failed, restarting it in {} s: {}", name, backoff.as_secs(), err);
+            if shutdown.cancel(sleep(backoff)).await.is_none() {
+                return;
+            }
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    })
+}
+
+/// Waits for SIGTERM, which `docker stop` sends, or Ctrl+C.
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => tokio::select! {
+                _ = terminate.recv() => {}
+                _ = tokio::signal::ctrl_c() => {}
+            },
+            Err(err) => {
+                eprintln!("Failed to listen for SIGTERM, only Ctrl+C stops the logger: {}", err);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+
+    #[tokio::test(start_paused = true)]
+    async fn restarts_with_backoff() {
+        let (_sender, shutdown) = Shutdown::new();
+        let runs = Arc::new(AtomicU32::new(0));
+        let counter = runs.clone();
+        let start = Instant::now();
+
+        let handle = supervise("test".to_string(), shutdown, move || {
+            let run = counter.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if run < 3 {
+                    panic!("run {}", run);
+                }
+            }
+        });
+        handle.await.unwrap();
+
+        assert_eq!(4, runs.load(Ordering::SeqCst));
+        // 1 + 2 + 4 seconds between the four runs
+        assert_eq!(Duration::from_secs(7), start.elapsed());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_stops_the_task() {
+        let (sender, shutdown) = Shutdown::new();
+        let task_shutdown = shutdown.clone();
+
+        let handle = supervise("test".to_string(), shutdown, move || {
+            let mut shutdown = task_shutdown.clone();
+            async move {
+                loop {
+                    if shutdown.cancel(sleep(Duration::from_secs(30))).await.is_none() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        sleep(Duration::from_secs(100)).await;
+        sender.send(true).unwrap();
+        handle.await.unwrap();
+    }
+}